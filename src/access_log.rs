@@ -0,0 +1,98 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use time::{macros::format_description, OffsetDateTime, UtcOffset};
+use tracing::error;
+
+use crate::http::{Request, Response};
+
+struct AccessLog {
+    file: Mutex<File>,
+    offset: UtcOffset,
+}
+
+static ACCESS_LOG: OnceLock<AccessLog> = OnceLock::new();
+
+/// Opens the access log for appending; until then, entries are discarded.
+///
+/// Must be called before any threads are spawned, as the local UTC offset can't be determined
+/// reliably afterwards.
+pub fn init(path: &Path) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
+    let log = AccessLog {
+        file: Mutex::new(file),
+        offset,
+    };
+    // A second initialization keeps the first file.
+    let _ = ACCESS_LOG.set(log);
+    Ok(())
+}
+
+/// Request data needed for an access log line, captured before the request is handled.
+pub struct Entry {
+    client: IpAddr,
+    request_line: String,
+    referer: String,
+    user_agent: String,
+}
+
+impl Entry {
+    pub fn new(client: IpAddr, request: &Request) -> Entry {
+        Entry {
+            client,
+            request_line: if request.simple {
+                format!("{} {}", request.method, request.path)
+            } else {
+                format!(
+                    "{} {} HTTP/1.{}",
+                    request.method, request.path, request.version
+                )
+            },
+            referer: quoted_header(request, "Referer"),
+            user_agent: quoted_header(request, "User-Agent"),
+        }
+    }
+
+    /// Writes the entry in NCSA Combined Log Format.
+    pub fn record(&self, response: &Response) {
+        let Some(log) = ACCESS_LOG.get() else { return };
+        let date = OffsetDateTime::now_utc()
+            .to_offset(log.offset)
+            .format(format_description!(
+                "[day]/[month repr:short]/[year]:[hour]:[minute]:[second] [offset_hour sign:mandatory][offset_minute]"
+            ))
+            .unwrap_or_default();
+        let bytes = match response.content_length() {
+            0 => "-".to_string(),
+            len => len.to_string(),
+        };
+        let line = format!(
+            "{} - - [{}] \"{}\" {} {} \"{}\" \"{}\"\n",
+            self.client,
+            date,
+            escape(&self.request_line),
+            response.status().code(),
+            bytes,
+            self.referer,
+            self.user_agent
+        );
+        let mut file = log.file.lock().unwrap_or_else(|err| err.into_inner());
+        file.write_all(line.as_bytes())
+            .unwrap_or_else(|err| error!("Error writing access log: {err}"));
+    }
+}
+
+fn quoted_header(request: &Request, name: &str) -> String {
+    match request.header(name) {
+        Some(value) => escape(&String::from_utf8_lossy(value)),
+        None => "-".into(),
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.escape_default().to_string()
+}
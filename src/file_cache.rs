@@ -0,0 +1,179 @@
+//! In-memory cache of small static files, kept up to date by their modification times.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use lru::LruCache;
+
+struct FileCache {
+    entries: Mutex<Entries>,
+    /// Files being loaded, whose content other requests wait for instead of reading them too.
+    flights: Mutex<HashMap<PathBuf, Arc<Flight>>>,
+    capacity: usize,
+    max_file_size: usize,
+}
+
+struct Entries {
+    files: LruCache<PathBuf, (Vec<u8>, SystemTime)>,
+    size: usize,
+}
+
+/// Content of a file being loaded, set once loading ends; `Some(None)` if it failed.
+#[derive(Default)]
+struct Flight {
+    content: Mutex<Option<Option<Vec<u8>>>>,
+    landed: Condvar,
+}
+
+/// Ends a flight once dropped, even if loading panicked, so waiters never wait forever.
+struct Landing<'a> {
+    cache: &'a FileCache,
+    path: &'a Path,
+    flight: Arc<Flight>,
+    content: Option<Vec<u8>>,
+}
+
+impl Drop for Landing<'_> {
+    fn drop(&mut self) {
+        let mut flights = self
+            .cache
+            .flights
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        flights.remove(self.path);
+        let mut content = self
+            .flight
+            .content
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+        *content = Some(self.content.take());
+        self.flight.landed.notify_all();
+    }
+}
+
+static CACHE: OnceLock<FileCache> = OnceLock::new();
+
+/// Enables the cache, holding up to `capacity` bytes of files no larger than `max_file_size`;
+/// until then, nothing is cached.
+pub fn init(capacity: usize, max_file_size: usize) {
+    let cache = FileCache {
+        entries: Mutex::new(Entries {
+            files: LruCache::unbounded(),
+            size: 0,
+        }),
+        flights: Mutex::new(HashMap::new()),
+        capacity,
+        max_file_size,
+    };
+    // A second initialization keeps the first cache.
+    let _ = CACHE.set(cache);
+}
+
+/// Whether a file of this size would be cached.
+pub fn is_eligible(size: u64) -> bool {
+    CACHE
+        .get()
+        .is_some_and(|cache| size <= cache.max_file_size as u64)
+}
+
+/// Returns the cached content of the file, if it was cached when last modified at `modified`.
+pub fn get(path: &Path, modified: SystemTime) -> Option<Vec<u8>> {
+    let mut entries = CACHE.get()?.entries.lock().unwrap();
+    match entries.files.get(&path.to_path_buf()) {
+        Some((content, cached)) if *cached == modified => Some(content.clone()),
+        _ => None,
+    }
+}
+
+/// Returns the cached content of the file as last modified at `modified`, or loads it with `load`
+/// and caches it. Concurrent calls for a file already being loaded wait for that load and share
+/// its content, so a popular file missing from the cache is read once. `None` means loading
+/// failed.
+pub fn get_or_load(
+    path: &Path,
+    modified: SystemTime,
+    load: impl FnOnce() -> Option<Vec<u8>>,
+) -> Option<Vec<u8>> {
+    let Some(cache) = CACHE.get() else {
+        return load();
+    };
+    let flight = {
+        let mut flights = cache.flights.lock().unwrap();
+        // Checked under the lock, as a flight ends only after caching what it loaded.
+        if let Some(content) = get(path, modified) {
+            return Some(content);
+        }
+        match flights.get(path) {
+            Some(flight) => Err(Arc::clone(flight)),
+            None => {
+                let flight = Arc::new(Flight::default());
+                flights.insert(path.to_path_buf(), Arc::clone(&flight));
+                Ok(flight)
+            }
+        }
+    };
+    let flight = match flight {
+        Ok(flight) => flight,
+        Err(flight) => {
+            let mut content = flight.content.lock().unwrap();
+            while content.is_none() {
+                content = flight.landed.wait(content).unwrap();
+            }
+            return content.clone().flatten();
+        }
+    };
+    let mut landing = Landing {
+        cache,
+        path,
+        flight,
+        content: None,
+    };
+    landing.content = load();
+    if let Some(content) = &landing.content {
+        insert(path, modified, content);
+    }
+    landing.content.clone()
+}
+
+/// Caches the content of the file as last modified at `modified`, evicting the least recently
+/// used files to make room.
+pub fn insert(path: &Path, modified: SystemTime, content: &[u8]) {
+    let Some(cache) = CACHE.get() else { return };
+    if content.len() > cache.max_file_size || content.len() > cache.capacity {
+        return;
+    }
+    let mut entries = cache.entries.lock().unwrap();
+    if let Some((replaced, _)) = entries
+        .files
+        .put(path.to_path_buf(), (content.to_vec(), modified))
+    {
+        entries.size -= replaced.len();
+    }
+    entries.size += content.len();
+    while entries.size > cache.capacity {
+        let Some((_, (evicted, _))) = entries.files.pop_lru() else {
+            break;
+        };
+        entries.size -= evicted.len();
+    }
+}
+
+/// Drops the cached file, or all cached files within the directory.
+pub fn invalidate(path: &Path) {
+    let Some(cache) = CACHE.get() else { return };
+    let mut entries = cache.entries.lock().unwrap();
+    let stale: Vec<_> = entries
+        .files
+        .iter()
+        .map(|(cached, _)| cached)
+        .filter(|cached| cached.starts_with(path))
+        .cloned()
+        .collect();
+    for cached in stale {
+        if let Some((content, _)) = entries.files.pop(&cached) {
+            entries.size -= content.len();
+        }
+    }
+}
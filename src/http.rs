@@ -1,38 +1,239 @@
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
+use std::fs::{File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{collections::HashMap, fmt::Display};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use sha2::{Digest, Sha256};
 use tracing::{debug, error};
 
-use crate::utils::match_file_type;
+use crate::utils::{is_fd_exhaustion, match_file_type};
 
 pub struct Request {
     pub method: String,
     pub path: String,
+    /// Minor version of HTTP/1.x.
     pub version: u8,
-    pub headers: HashMap<String, Vec<u8>>,
+    /// Values of each header, in the order received. Names are lowercased, since HTTP treats
+    /// them case-insensitively.
+    pub headers: HashMap<String, Vec<Vec<u8>>>,
+    /// Whether this is an HTTP/0.9 simple request, answered with nothing but the content.
+    pub simple: bool,
 }
 
 impl Request {
-    pub fn new(req: httparse::Request) -> Request {
-        let headers: HashMap<_, _> = req
-            .headers
-            .iter_mut()
-            .map(|header| (header.name.into(), header.value.into()))
-            .collect();
-        Request {
-            method: req.method.unwrap().to_owned(),
-            path: req.path.unwrap().to_owned(),
-            version: req.version.unwrap().to_owned(),
+    /// Takes a parsed request, or returns `None` if its request line is incomplete.
+    pub fn new(req: httparse::Request) -> Option<Request> {
+        let mut headers: HashMap<_, Vec<_>> = HashMap::new();
+        for header in req.headers.iter() {
+            headers
+                .entry(header.name.to_ascii_lowercase())
+                .or_default()
+                .push(header.value.into());
+        }
+        Some(Request {
+            method: req.method?.to_owned(),
+            path: req.path?.to_owned(),
+            version: req.version?,
             headers,
+            simple: false,
+        })
+    }
+
+    pub fn simple(path: String) -> Request {
+        Request {
+            method: "GET".into(),
+            path,
+            version: 0,
+            headers: HashMap::new(),
+            simple: true,
         }
     }
+
+    /// The path of the target, without its query.
+    pub fn path_without_query(&self) -> &str {
+        self.path
+            .split_once('?')
+            .map_or(&self.path, |(path, _)| path)
+    }
+
+    /// The query of the target, without its `?`.
+    pub fn query(&self) -> Option<&str> {
+        self.path.split_once('?').map(|(_, query)| query)
+    }
+
+    /// Looks up the first value of a header, matching its name case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.header_values(name).next()
+    }
+
+    /// Looks up all values of a header, matching its name case-insensitively.
+    pub fn header_values(&self, name: &str) -> impl Iterator<Item = &[u8]> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .into_iter()
+            .flatten()
+            .map(Vec::as_slice)
+    }
+}
+
+/// Strong entity tag of a file sent without reading it, made of its modification time and size.
+/// Files may claim to be modified before the epoch, which gets a sign.
+fn file_etag(metadata: &Metadata) -> String {
+    let modified = match metadata
+        .modified()
+        .map(|time| time.duration_since(UNIX_EPOCH))
+    {
+        Ok(Ok(since)) => format!("{:x}.{:x}", since.as_secs(), since.subsec_nanos()),
+        Ok(Err(before)) => {
+            let before = before.duration();
+            format!("-{:x}.{:x}", before.as_secs(), before.subsec_nanos())
+        }
+        Err(_) => String::new(),
+    };
+    format!("\"{modified}-{:x}\"", metadata.len())
 }
 
+/// Digests of files sent from disk, by path, with the entity tag of the version digested, so
+/// large files aren't hashed again on every request.
+type FileDigests = HashMap<PathBuf, (Vec<u8>, String)>;
+
+static FILE_DIGESTS: OnceLock<Mutex<FileDigests>> = OnceLock::new();
+
+/// Headers added to every response when the security preset is enabled.
+pub const SECURITY_HEADERS: [(&str, &str); 4] = [
+    ("X-Content-Type-Options", "nosniff"),
+    ("X-Frame-Options", "SAMEORIGIN"),
+    ("Referrer-Policy", "strict-origin-when-cross-origin"),
+    (
+        "Permissions-Policy",
+        "camera=(), geolocation=(), microphone=(), payment=(), usb=()",
+    ),
+];
+
 pub struct Response {
     status: Status,
     headers: HashMap<String, Vec<u8>>,
     content: Option<Vec<u8>>,
+    /// Content sent straight from a file instead, without reading it into memory.
+    file: Option<FileSlice>,
+    /// Content produced while it is sent instead, its length unknown upfront.
+    stream: Option<Box<dyn Read + Send>>,
+}
+
+/// Content of a response written after its head.
+pub enum Tail {
+    File(FileSlice),
+    Stream(Box<dyn Read + Send>),
+}
+
+/// Size of chunks in which content of unknown length is sent, in bytes.
+const STREAM_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Copies content of unknown length to the writer, framed in chunks so its end can be told.
+/// Returns the number of bytes written.
+pub fn copy_chunked(reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<u64> {
+    let mut chunk = vec![0; STREAM_CHUNK_SIZE];
+    let mut written = 0;
+    loop {
+        let count = match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        let size = format!("{count:x}\r\n");
+        writer.write_all(size.as_bytes())?;
+        writer.write_all(&chunk[..count])?;
+        writer.write_all(b"\r\n")?;
+        written += (size.len() + count + 2) as u64;
+    }
+    let last = b"0\r\n\r\n";
+    writer.write_all(last)?;
+    Ok(written + last.len() as u64)
+}
+
+/// Part of a file sent as response content.
+pub struct FileSlice {
+    file: File,
+    offset: u64,
+    length: u64,
+}
+
+impl FileSlice {
+    pub fn len(&self) -> u64 {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Copies the slice into the writer, through a buffer of `chunk_size` bytes.
+    fn copy_to<W: Write>(&self, writer: &mut W, chunk_size: usize) -> io::Result<()> {
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut chunk = vec![0; chunk_size];
+        let mut remaining = self.length;
+        while remaining > 0 {
+            let count = remaining.min(chunk_size as u64) as usize;
+            match file.read(&mut chunk[..count]) {
+                // The file got shorter since it was opened.
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(read) => {
+                    writer.write_all(&chunk[..read])?;
+                    remaining -= read as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the slice to the connection, letting the kernel copy it with `sendfile`, at most
+    /// `chunk_size` bytes per call.
+    #[cfg(target_os = "linux")]
+    pub fn send_to(&self, stream: &mut TcpStream, chunk_size: usize) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let mut offset = libc::off_t::try_from(self.offset)
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let mut remaining = self.length;
+        while remaining > 0 {
+            let count = remaining.min(chunk_size as u64) as usize;
+            // SAFETY: both descriptors stay open during the call, and `offset` is a valid `off_t`
+            // for it to advance.
+            let sent = unsafe {
+                libc::sendfile(
+                    stream.as_raw_fd(),
+                    self.file.as_raw_fd(),
+                    &mut offset,
+                    count,
+                )
+            };
+            match sent {
+                -1 => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+                // The file got shorter since it was opened.
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                sent => remaining -= sent as u64,
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the slice to the connection, `chunk_size` bytes at a time.
+    #[cfg(not(target_os = "linux"))]
+    pub fn send_to(&self, stream: &mut TcpStream, chunk_size: usize) -> io::Result<()> {
+        self.copy_to(stream, chunk_size)
+    }
 }
 
 impl Response {
@@ -43,6 +244,8 @@ impl Response {
             status,
             headers,
             content: None,
+            file: None,
+            stream: None,
         }
     }
 
@@ -57,17 +260,71 @@ impl Response {
         resp
     }
 
-    pub fn render(mut self) -> Vec<u8> {
+    /// Renders the response; content to be sent from a file or a stream is returned separately,
+    /// to be written after the rest.
+    pub fn render(mut self) -> (Vec<u8>, Option<Tail>) {
+        // Without a length, a bodyless response would only end when the connection does.
+        let delimited = self.stream.is_some() || self.headers.contains_key("Transfer-Encoding");
+        if !matches!(self.status, Status::NoContent) && !delimited {
+            self.headers
+                .entry("Content-Length".into())
+                .or_insert_with(|| "0".into());
+        }
         let status_line = self.status_line();
         let mut lines = Vec::with_capacity(self.headers.len() + 3);
         lines.push(status_line.into());
         let headers = self.headers.drain().map(Response::render_header);
         lines.extend(headers);
         lines.push(vec![]);
-        if let Some(content) = self.content {
-            lines.push(content);
+        lines.push(self.content.unwrap_or_default());
+        let tail = match (self.file, self.stream) {
+            (Some(file), _) => Some(Tail::File(file)),
+            (None, Some(stream)) => Some(Tail::Stream(stream)),
+            (None, None) => None,
+        };
+        (lines.join("\r\n".as_bytes()), tail)
+    }
+
+    /// The content alone, as HTTP/0.9 responses have neither status line nor headers, with
+    /// what is sent after it as for `render`.
+    pub fn render_simple(self) -> (Vec<u8>, Option<Tail>) {
+        let tail = match (self.file, self.stream) {
+            (Some(file), _) => Some(Tail::File(file)),
+            (None, Some(stream)) => Some(Tail::Stream(stream)),
+            (None, None) => None,
+        };
+        (self.content.unwrap_or_default(), tail)
+    }
+
+    /// Sets content read from `reader` while the response is sent, for when its length isn't
+    /// known upfront. It is sent in chunks, or delimited by closing the connection.
+    pub fn stream_content<R>(&mut self, reader: R)
+    where
+        R: Read + Send + 'static,
+    {
+        self.remove_header("Content-Length");
+        self.stream = Some(Box::new(reader));
+    }
+
+    pub fn is_streamed(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    pub fn content(&self) -> Option<&[u8]> {
+        self.content.as_deref()
+    }
+
+    /// Length of the content, which is unknown (and taken as 0) for streamed content.
+    pub fn content_length(&self) -> u64 {
+        match (&self.content, &self.file) {
+            (_, Some(file)) => file.len(),
+            (Some(content), None) => content.len() as u64,
+            (None, None) => 0,
         }
-        lines.join("\r\n".as_bytes())
     }
 
     pub fn status_line(&self) -> String {
@@ -75,16 +332,43 @@ impl Response {
     }
 
     fn render_header((name, value): (String, Vec<u8>)) -> Vec<u8> {
-        let new_value = unsafe { String::from_utf8_unchecked(value) };
-        format!("{}: {}", name, new_value).into()
+        let mut line = name.into_bytes();
+        line.extend_from_slice(b": ");
+        line.extend(value);
+        line
     }
 
+    /// Sets the header, replacing any value it had under any casing of its name.
     pub fn set_header<H, V>(&mut self, name: H, value: V)
     where
         H: Into<String>,
         V: Into<Vec<u8>>,
     {
-        self.headers.insert(name.into(), value.into());
+        let name = name.into();
+        self.headers
+            .retain(|existing, _| !existing.eq_ignore_ascii_case(&name));
+        self.headers.insert(name, value.into());
+    }
+
+    /// Adds a value to a list-valued header, such as `Vary`, keeping the ones already set.
+    pub fn append_header<V>(&mut self, name: &str, value: V)
+    where
+        V: Into<Vec<u8>>,
+    {
+        let value = value.into();
+        match self.headers.get_mut(name) {
+            Some(existing) => {
+                existing.extend_from_slice(b", ");
+                existing.extend(value);
+            }
+            None => {
+                self.headers.insert(name.into(), value);
+            }
+        }
+    }
+
+    pub fn remove_header(&mut self, name: &str) {
+        self.headers.remove(name);
     }
 
     pub fn add_content<C>(&mut self, content: C)
@@ -101,20 +385,98 @@ impl Response {
         self.content = Some(content);
     }
 
-    pub fn load_file(mut self, path: &Path) -> Response {
-        let mut file = match File::open(path) {
+    /// Adds a SHA-256 `Repr-Digest` of the content, letting clients verify what they received.
+    /// A file sent from disk, found at `path`, is read in chunks of `chunk_size` bytes, and its
+    /// digest is kept for as long as its entity tag stays the same.
+    pub fn set_repr_digest(&mut self, path: &Path, chunk_size: usize) {
+        let digest = if let Some(file) = &self.file {
+            let Some(etag) = self.headers.get("ETag").cloned() else {
+                return;
+            };
+            let digests = FILE_DIGESTS.get_or_init(Mutex::default);
+            let cached = digests
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .get(path)
+                .filter(|(tag, _)| *tag == etag)
+                .map(|(_, digest)| digest.clone());
+            if let Some(digest) = cached {
+                digest
+            } else {
+                let mut hasher = Sha256::new();
+                if let Err(err) = file.copy_to(&mut hasher, chunk_size) {
+                    error!("Failed to digest file: {err}");
+                    return;
+                }
+                let digest = BASE64.encode(hasher.finalize());
+                digests
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .insert(path.into(), (etag, digest.clone()));
+                digest
+            }
+        } else if let Some(content) = &self.content {
+            BASE64.encode(Sha256::digest(content))
+        } else {
+            return;
+        };
+        self.set_header("Repr-Digest", format!("sha-256=:{digest}:"));
+    }
+
+    /// Opens the file to be sent straight from it, rather than reading it into memory first.
+    pub fn send_file(mut self, path: &Path) -> Response {
+        let file = match File::open(path) {
             Ok(file) => file,
+            Err(err) if is_fd_exhaustion(&err) => return out_of_descriptors(),
             Err(err) => {
                 return server_error(format!("Error on opening file {}: {}", path.display(), err))
             }
         };
-        let mut buffer = Vec::new();
-        match file.read_to_end(&mut buffer) {
-            Ok(_) => (),
+        let metadata = match file.metadata() {
+            Ok(metadata) => metadata,
             Err(err) => {
                 return server_error(format!("Error on reading file {}: {}", path.display(), err))
             }
         };
+        let length = metadata.len();
+        self.set_header("Content-Length", length.to_string());
+        self.set_header("ETag", file_etag(&metadata));
+        self.set_header("Content-Type", match_file_type(path));
+        self.set_modified(&file, path);
+        self.file = Some(FileSlice {
+            file,
+            offset: 0,
+            length,
+        });
+
+        debug!("File {} opened", path.display());
+        self
+    }
+
+    pub fn load_file(mut self, path: &Path, chunk_size: usize) -> Response {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if is_fd_exhaustion(&err) => return out_of_descriptors(),
+            Err(err) => {
+                return server_error(format!("Error on opening file {}: {}", path.display(), err))
+            }
+        };
+        let mut buffer = Vec::new();
+        let mut chunk = vec![0; chunk_size];
+        loop {
+            match file.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(bytes_read) => buffer.extend_from_slice(&chunk[..bytes_read]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    return server_error(format!(
+                        "Error on reading file {}: {}",
+                        path.display(),
+                        err
+                    ))
+                }
+            }
+        }
 
         self.add_content(buffer);
         self.set_header("Content-Type", match_file_type(path));
@@ -124,11 +486,18 @@ impl Response {
         self
     }
 
+    /// Sets content read from the file earlier, with the headers `load_file` would set.
+    pub fn add_file_content(&mut self, path: &Path, content: Vec<u8>, modified: SystemTime) {
+        self.add_content(content);
+        self.set_header("Content-Type", match_file_type(path));
+        self.set_last_modified(modified);
+    }
+
     fn set_modified(&mut self, file: &File, path: &Path) {
         match file.metadata() {
             Ok(metadata) => {
                 let modified = metadata.modified().expect("Unsupported platform");
-                self.set_header("Last-Modified", httpdate::fmt_http_date(modified));
+                self.set_last_modified(modified);
             }
             Err(err) => {
                 error!("Failed to load file {} metadata: {}", path.display(), err);
@@ -136,43 +505,227 @@ impl Response {
         }
     }
 
+    /// Sets `Last-Modified`, unless the time is out of the years HTTP dates cover.
+    fn set_last_modified(&mut self, modified: SystemTime) {
+        // The first second of year 10000.
+        const END: Duration = Duration::from_secs(253_402_300_800);
+        match modified.duration_since(UNIX_EPOCH) {
+            Ok(since) if since < END => {
+                self.set_header("Last-Modified", httpdate::fmt_http_date(modified));
+            }
+            _ => debug!("Modification time out of HTTP date range"),
+        }
+    }
+
+    /// Whether the content is still what an `If-Range` header value, an entity tag or a date,
+    /// describes, so a range of it may be sent. Weak entity tags never match, since ranges of
+    /// merely equivalent contents don't fit together.
+    pub fn matches_if_range(&self, validator: &[u8]) -> bool {
+        let validator = validator.trim_ascii();
+        if validator.starts_with(b"\"") {
+            return self.has_strong_etag(validator);
+        }
+        if validator.starts_with(b"W/") {
+            return false;
+        }
+        let parse_date =
+            |date: &[u8]| httpdate::parse_http_date(std::str::from_utf8(date).ok()?).ok();
+        match (self.headers.get("Last-Modified"), parse_date(validator)) {
+            (Some(modified), Some(date)) => parse_date(modified) == Some(date),
+            _ => false,
+        }
+    }
+
+    /// Whether the content is one an `If-Match` header value allows changing or sending: any,
+    /// for `*`, or else one of the listed entity tags, compared strongly.
+    pub fn matches_if_match(&self, condition: &[u8]) -> bool {
+        let condition = condition.trim_ascii();
+        condition == b"*"
+            || condition
+                .split(|&b| b == b',')
+                .any(|etag| self.has_strong_etag(etag.trim_ascii()))
+    }
+
+    /// Whether the content has a strong entity tag, and it's the given one.
+    fn has_strong_etag(&self, etag: &[u8]) -> bool {
+        let own = self.headers.get("ETag").map(Vec::as_slice);
+        !etag.starts_with(b"W/") && own == Some(etag)
+    }
+
+    /// Narrows the content to the single byte range requested by a `Range` header value. Ranges
+    /// which cannot be parsed, or multiple ones, are ignored and the whole content is kept.
+    pub fn select_range(mut self, range: &[u8]) -> Response {
+        let length = self.content_length();
+        if self.content.is_none() && self.file.is_none() {
+            return self;
+        }
+        match parse_byte_range(range, length) {
+            None => self,
+            Some(Some((first, last))) => {
+                self.status = Status::PartialContent;
+                self.set_header("Content-Range", format!("bytes {first}-{last}/{length}"));
+                self.set_header("Content-Length", (last - first + 1).to_string());
+                if let Some(file) = &mut self.file {
+                    file.offset += first;
+                    file.length = last - first + 1;
+                } else if let Some(content) = &mut self.content {
+                    *content = content[first as usize..=last as usize].to_vec();
+                }
+                self
+            }
+            Some(None) => {
+                let mut resp = Response::new(Status::RangeNotSatisfiable);
+                resp.set_header("Content-Range", format!("bytes */{length}"));
+                resp
+            }
+        }
+    }
+
+    /// Drops the content, keeping all headers describing it, as in a response to HEAD. Those
+    /// include the length of the content, even if it wasn't set yet.
     pub fn to_head(mut self) -> Response {
+        if self.content.is_some() || self.file.is_some() {
+            let length = self.content_length().to_string();
+            self.headers
+                .entry("Content-Length".into())
+                .or_insert_with(|| length.into());
+        }
+        if self.stream.take().is_some() {
+            // As the content would be sent to HTTP/1.1 clients keeping the connection.
+            self.set_header("Transfer-Encoding", "chunked");
+        }
         self.content = None;
+        self.file = None;
         self
     }
 }
 
 #[derive(Clone, Copy)]
 pub enum Status {
+    Continue,
     Ok,
+    NoContent,
+    PartialContent,
     Moved,
     BadRequest,
+    Unauthorized,
     Forbidden,
     NotFound,
     MethodNotAllowed,
+    NotAcceptable,
     RequestTimeout,
     RequestURITooLong,
+    PreconditionFailed,
+    RangeNotSatisfiable,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
     InternalServerError,
     NotImplemented,
+    ServiceUnavailable,
     HTTPVersionNotSupported,
 }
 
 impl Status {
     pub fn code(&self) -> u16 {
         match self {
+            Status::Continue => 100,
             Status::Ok => 200,
+            Status::NoContent => 204,
+            Status::PartialContent => 206,
             Status::Moved => 301,
             Status::BadRequest => 400,
+            Status::Unauthorized => 401,
             Status::Forbidden => 403,
             Status::NotFound => 404,
             Status::MethodNotAllowed => 405,
+            Status::NotAcceptable => 406,
             Status::RequestTimeout => 408,
             Status::RequestURITooLong => 415,
+            Status::PreconditionFailed => 412,
+            Status::RangeNotSatisfiable => 416,
+            Status::TooManyRequests => 429,
+            Status::RequestHeaderFieldsTooLarge => 431,
             Status::InternalServerError => 500,
             Status::NotImplemented => 501,
+            Status::ServiceUnavailable => 503,
             Status::HTTPVersionNotSupported => 505,
         }
     }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Status::Continue => "Continue",
+            Status::Ok => "OK",
+            Status::NoContent => "No Content",
+            Status::PartialContent => "Partial Content",
+            Status::Moved => "Moved Permanently",
+            Status::BadRequest => "Bad Request",
+            Status::Unauthorized => "Unauthorized",
+            Status::Forbidden => "Forbidden",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::NotAcceptable => "Not Acceptable",
+            Status::RequestTimeout => "Request Timeout",
+            Status::RequestURITooLong => "URI Too Long",
+            Status::PreconditionFailed => "Precondition Failed",
+            Status::RangeNotSatisfiable => "Range Not Satisfiable",
+            Status::TooManyRequests => "Too Many Requests",
+            Status::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            Status::InternalServerError => "Internal Server Error",
+            Status::NotImplemented => "Not Implemented",
+            Status::ServiceUnavailable => "Service Unavailable",
+            Status::HTTPVersionNotSupported => "HTTP Version Not Supported",
+        }
+    }
+}
+
+/// Parses a single `bytes` range into inclusive bounds within content of the given length.
+/// Returns `None` when the value should be ignored and `Some(None)` when it cannot be satisfied.
+fn parse_byte_range(value: &[u8], length: u64) -> Option<Option<(u64, u64)>> {
+    let spec = std::str::from_utf8(value).ok()?.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    if first.is_empty() {
+        // A suffix range: the last `last` bytes.
+        let suffix: u64 = last.parse().ok()?;
+        if suffix == 0 || length == 0 {
+            return Some(None);
+        }
+        return Some(Some((length.saturating_sub(suffix), length - 1)));
+    }
+    let first: u64 = first.parse().ok()?;
+    let last = match last {
+        "" => u64::MAX,
+        last => last.parse().ok()?,
+    };
+    if last < first {
+        return None;
+    }
+    if first >= length {
+        return Some(None);
+    }
+    Some(Some((first, last.min(length - 1))))
+}
+
+/// A response for when no file descriptors are left, which itself must not need one.
+pub fn out_of_descriptors() -> Response {
+    error!("Out of file descriptors");
+    let mut resp = Response::new(Status::ServiceUnavailable);
+    resp.set_header("Retry-After", "1");
+    resp
+}
+
+/// Answers a client over its rate limit, which may retry after `wait`.
+pub fn too_many_requests(wait: Duration) -> Response {
+    let mut resp = Response::new(Status::TooManyRequests);
+    resp.set_header(
+        "Retry-After",
+        wait.as_secs_f64().ceil().max(1.0).to_string(),
+    );
+    resp
 }
 
 pub fn server_error<M>(msg: M) -> Response
@@ -1,20 +1,33 @@
+pub mod access_log;
+pub mod file_cache;
 pub mod http;
 pub mod logging;
+pub mod metrics;
+pub mod parser;
+pub mod rate_limit;
 pub mod reader;
+pub mod server;
 pub mod static_server;
 pub mod utils;
+pub mod watch;
+
+pub use server::Server;
 
 use std::collections::HashMap;
 use std::fs::{canonicalize, read_dir, File};
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use clap::Parser;
+use clap::{ArgAction, Parser};
+use ipnet::IpNet;
 use tracing::warn;
 
 pub struct ServerState<'a> {
     pub config: Config,
-    pub hosts: HashMap<String, (DomainHandler<'a>, crossbeam_channel::Receiver<()>)>,
+    pub hosts: HashMap<String, DomainHandler<'a>>,
+    pub(crate) connections: server::OpenConnections,
+    pub(crate) rate_limiter: Option<rate_limit::RateLimiter>,
 }
 
 pub enum DomainHandler<'a> {
@@ -24,8 +37,9 @@ pub enum DomainHandler<'a> {
 
 pub trait HostData<'a> {
     fn get_config(&self) -> &Config;
-    fn get_address(&self) -> &SocketAddr;
+    fn get_addresses(&self) -> &[SocketAddr];
     fn get_hostname(&self) -> &String;
+    fn get_traffic(&self) -> &metrics::HostTraffic;
 }
 
 impl HostData<'_> for DomainHandler<'_> {
@@ -36,9 +50,9 @@ impl HostData<'_> for DomainHandler<'_> {
         }
     }
 
-    fn get_address(&self) -> &SocketAddr {
+    fn get_addresses(&self) -> &[SocketAddr] {
         match self {
-            Self::StaticDir(data) => data.get_address(),
+            Self::StaticDir(data) => data.get_addresses(),
             Self::Executable(_) => panic!("Not supported yet"),
         }
     }
@@ -49,6 +63,13 @@ impl HostData<'_> for DomainHandler<'_> {
             Self::Executable(_) => panic!("Not supported yet"),
         }
     }
+
+    fn get_traffic(&self) -> &metrics::HostTraffic {
+        match self {
+            Self::StaticDir(data) => data.get_traffic(),
+            Self::Executable(_) => panic!("Not supported yet"),
+        }
+    }
 }
 
 /// Simple, near-minimal static HTTP server.
@@ -56,28 +77,351 @@ impl HostData<'_> for DomainHandler<'_> {
 /// Detailed notes on usage are included in the README.
 #[derive(Parser)]
 pub struct Config {
-    /// Path to directory containg content to be hosted
-    #[arg(value_parser = Config::verify_dir)]
+    /// Path to directory containg content to be hosted, or to a single file served as the
+    /// response to every request by localhost
+    #[arg(value_parser = Config::verify_path)]
     pub directory: PathBuf,
 
-    /// Port under which content is served.
-    #[arg(short, long)]
-    pub port: u16,
+    /// Port under which each host is served at the address its name resolves to
+    #[arg(short, long, required_unless_present = "listen")]
+    pub port: Option<u16>,
+
+    /// Bind hosts to this interface, as in `0.0.0.0` or `::`, instead of the address their names
+    /// resolve to
+    #[arg(long, value_name = "IP", requires = "port")]
+    pub bind: Option<IpAddr>,
+
+    /// Listen on IPv6 addresses for IPv6 connections only; by default, they accept IPv4 ones too
+    #[arg(long)]
+    pub ipv6_only: bool,
 
-    /// How long to keep TCP connection active, in seconds
+    /// Also serve all hosts at this address, as in `0.0.0.0:8080`; may be repeated
+    #[arg(long, value_name = "ADDR:PORT")]
+    pub listen: Vec<SocketAddr>,
+
+    /// How long to keep TCP connection active, in seconds; 0 closes it after one request
     #[arg(long, default_value_t = 2)]
     pub keep_alive: u8,
 
+    /// How long to keep TCP connection active, in milliseconds; overrides --keep-alive
+    #[arg(long, value_name = "MS", conflicts_with = "keep_alive")]
+    pub keep_alive_ms: Option<u64>,
+
+    /// How long a connection may stay open however busy it is, in seconds; the response in progress
+    /// once it has passed closes it. 0 means no limit
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    pub connection_max_duration: u64,
+
+    /// How long a request may take to arrive once its first bytes do, in seconds; 0 means no limit
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    pub request_timeout: u64,
+
+    /// How long the head of a request may take to arrive once its first bytes do, in seconds;
+    /// 0 leaves it to the request timeout
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    pub header_timeout: u64,
+
+    /// How long connections may take to finish once shutdown starts, in seconds, before they are
+    /// closed; 0 means no limit
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    pub shutdown_timeout: u64,
+
+    /// Answer TRACE requests with an echo of them; off by default, as echoing requests helps
+    /// cross-site tracing attacks
+    #[arg(long)]
+    pub enable_trace: bool,
+
+    /// Accept obsolete HTTP/0.9 simple requests (`GET /path` with no version), instead of
+    /// rejecting them as malformed
+    #[arg(long = "allow-http-0.9")]
+    pub allow_http09: bool,
+
     /// Maximal number of headers included in a request
     #[arg(long, default_value_t = 512)]
     pub max_headers_number: usize,
 
-    /// How many concurrent requests can one host handle
-    #[arg(long, default_value_t = 4)]
-    pub threads_per_connection: u8,
+    /// Maximal size of a request's head (request line and headers), in bytes; 0 means no limit
+    #[arg(long, value_name = "BYTES", default_value_t = 64 * 1024)]
+    pub max_header_bytes: usize,
+
+    /// Threads handling connections of each listening address, which is how many connections it
+    /// serves at once; with the shared concurrency model, of all addresses together. Formerly
+    /// `--threads-per-connection`, which is still accepted
+    #[arg(
+        long,
+        value_name = "COUNT",
+        default_value_t = 4,
+        alias = "threads-per-connection"
+    )]
+    pub worker_threads: u8,
+
+    /// How connections are distributed among worker threads
+    #[arg(long, value_enum, default_value_t = ConcurrencyModel::PerHost)]
+    pub concurrency_model: ConcurrencyModel,
+
+    /// Size of chunks in which served files are read and sent, in bytes (4 KiB to 16 MiB)
+    #[arg(long, default_value_t = 64 * 1024, value_parser = clap::value_parser!(u32).range(4 * 1024..=16 * 1024 * 1024))]
+    pub file_chunk_size: u32,
+
+    /// Answer GET and HEAD requests for this exact path with 200 OK, without touching the disk
+    #[arg(long, value_name = "PATH")]
+    pub health_path: Option<String>,
+
+    /// Require HTTP Basic authentication with this user name (health and metrics paths are exempt)
+    #[arg(long, requires = "auth_pass")]
+    pub auth_user: Option<String>,
+
+    /// Password for HTTP Basic authentication
+    #[arg(long, requires = "auth_user", env = "WEBSERVER_AUTH_PASS", hide_env_values = true)]
+    pub auth_pass: Option<String>,
+
+    /// Only accept clients from this network (CIDR or single address); may be repeated.
+    /// When given, clients outside all allowed networks are refused
+    #[arg(long, value_name = "CIDR", value_parser = utils::parse_network)]
+    pub allow: Vec<IpNet>,
+
+    /// Refuse clients from this network (CIDR or single address), even if allowed; may be repeated
+    #[arg(long, value_name = "CIDR", value_parser = utils::parse_network)]
+    pub deny: Vec<IpNet>,
+
+    /// Close connections of refused clients without answering them with 403 Forbidden
+    #[arg(long)]
+    pub drop_denied: bool,
+
+    /// Reverse proxy (CIDR or single address) trusted to name the client in the last
+    /// X-Forwarded-For entry, used for logging, rate limiting and access control; may be repeated
+    #[arg(long = "trusted-proxy", value_name = "CIDR", value_parser = utils::parse_network)]
+    pub trusted_proxies: Vec<IpNet>,
+
+    /// Allow cross-origin requests from this origin, or from any with `*`; may be repeated
+    #[arg(long = "cors-origin", value_name = "ORIGIN")]
+    pub cors_origins: Vec<String>,
+
+    /// Serve Prometheus metrics under this exact path
+    #[arg(long, value_name = "PATH")]
+    pub metrics_path: Option<String>,
+
+    /// Warn about requests taking longer than this to handle and respond to, in milliseconds;
+    /// 0 disables the warnings
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    pub slow_request_threshold: u64,
+
+    /// Total size of request buffers across all connections, in bytes; requests which would
+    /// exceed it are answered with 503 Service Unavailable. Responses are not counted. 0 means
+    /// no limit
+    #[arg(long, value_name = "BYTES", default_value_t = 0)]
+    pub max_buffer_memory: usize,
+
+    /// Raise the limit of open file descriptors to this many at startup, as far as the hard limit
+    /// allows
+    #[arg(long, value_name = "N")]
+    pub max_open_files: Option<u64>,
+
+    /// While this file exists, answer requests with 503 Service Unavailable
+    #[arg(long, value_name = "PATH")]
+    pub maintenance_file: Option<PathBuf>,
+
+    /// Limit maintenance to paths under this prefix; may be repeated
+    #[arg(long = "maintenance-path", value_name = "PREFIX")]
+    pub maintenance_paths: Vec<String>,
+
+    /// Append a Combined Log Format line per handled request to this file
+    #[arg(long, value_name = "PATH")]
+    pub access_log: Option<PathBuf>,
+
+    /// Directory for the JSON log files, started anew each day
+    #[arg(long, value_name = "DIR", default_value = "logs")]
+    pub log_dir: PathBuf,
+
+    /// Don't write log files, only log to standard output
+    #[arg(long)]
+    pub no_log_file: bool,
+
+    /// Start a new log file once the current one would grow over this size, in bytes; 0 means
+    /// no limit
+    #[arg(long, value_name = "BYTES", default_value_t = 0)]
+    pub log_max_size: u64,
+
+    /// Number of log files kept in the log directory, deleting the oldest ones; 0 keeps all
+    #[arg(long, value_name = "COUNT", default_value_t = 0)]
+    pub log_keep: usize,
+
+    /// Format of logs written to standard output
+    #[arg(long, value_enum, default_value_t = LogFormat::Pretty)]
+    pub log_format: LogFormat,
+
+    /// Log more to standard output: debug messages, then traces; may be repeated. A level set in
+    /// RUST_LOG takes precedence
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Log less to standard output: only warnings, then errors, then nothing; may be repeated
+    #[arg(short, long, action = ArgAction::Count)]
+    pub quiet: u8,
+
+    /// Page served for the given status instead of {status_code}.html, as in
+    /// `404=errors/not-found.html`; relative to the content directory. May be repeated
+    #[arg(long = "error-page", value_name = "CODE=PATH", value_parser = Config::parse_error_page)]
+    pub error_pages: Vec<(u16, PathBuf)>,
+
+    /// HTML page served for errors without their own {status_code}.html page; `{{status}}` and
+    /// `{{reason}}` in it are replaced with the status code and reason phrase
+    #[arg(long, value_name = "PATH", value_parser = Config::read_template)]
+    pub default_error_template: Option<String>,
+
+    /// Keep up to this many bytes of served files in memory; 0 disables the cache
+    #[arg(long, value_name = "BYTES", default_value_t = 0)]
+    pub cache_size_bytes: usize,
+
+    /// Largest file kept in the cache, in bytes
+    #[arg(long, value_name = "BYTES", default_value_t = 256 * 1024)]
+    pub cache_max_file_bytes: usize,
+
+    /// Watch content directories, logging changes and dropping changed files from the cache
+    #[arg(long)]
+    pub watch: bool,
+
+    /// Files at least this large, in bytes, are sent straight from disk (with sendfile on Linux)
+    /// instead of being read into memory first; 0 reads all files into memory
+    #[arg(long, value_name = "BYTES", default_value_t = 1024 * 1024)]
+    pub sendfile_threshold: u64,
+
+    /// Answer clients sending more than this many requests per second with 429; 0 means no limit
+    #[arg(long, value_name = "REQS_PER_SEC", default_value_t = 0)]
+    pub rate_limit: u32,
+
+    /// Add a Repr-Digest header with the SHA-256 of served files
+    #[arg(long)]
+    pub repr_digest: bool,
+
+    /// Add a bundle of hardening headers (nosniff, SAMEORIGIN framing, referrer and permissions
+    /// policies) to every response
+    #[arg(long)]
+    pub security_headers: bool,
+
+    /// Add a Strict-Transport-Security header asking browsers to use HTTPS for a year
+    #[arg(long)]
+    pub hsts: bool,
+
+    /// Add `X-Content-Type-Options: nosniff`, so browsers keep to the declared content types
+    #[arg(long)]
+    pub no_sniff: bool,
+
+    /// Value of the X-Frame-Options header, as in `DENY` or `SAMEORIGIN`
+    #[arg(long, value_name = "VALUE")]
+    pub frame_options: Option<String>,
+
+    /// Content type of files with this extension, as in `wasm=application/wasm`, overriding the
+    /// guessed one; may be repeated
+    #[arg(long = "mime", value_name = "EXT=TYPE", value_parser = Config::parse_mime)]
+    pub mime_overrides: Vec<(String, mime_guess::Mime)>,
+
+    /// Content type of files whose type isn't known, as in `text/plain` for extensionless ones
+    #[arg(long, value_name = "TYPE", default_value = "application/octet-stream")]
+    pub default_mime: mime_guess::Mime,
+
+    /// Add this header to every response, as in `Content-Security-Policy: default-src 'self'`,
+    /// replacing any other value; may be repeated
+    #[arg(long = "header", value_name = "NAME: VALUE", value_parser = Config::parse_header)]
+    pub extra_headers: Vec<(String, String)>,
+
+    /// Path prefix the server is mounted under by a proxy, as in `/app`; it is stripped from
+    /// requests (which are 404 without it) and added to generated URLs
+    #[arg(long, value_name = "PATH", value_parser = Config::parse_base_path)]
+    pub base_path: Option<String>,
+
+    /// Answer requests for missing paths without an extension with the host's index.html, as
+    /// single-page applications route them on the client
+    #[arg(long)]
+    pub spa_fallback: bool,
+
+    /// What the Server header tells about the server
+    #[arg(long, value_enum, default_value_t = ServerTokens::Name)]
+    pub server_tokens: ServerTokens,
+
+    /// Value of the Server header, replacing what `--server-tokens` would put there
+    #[arg(long, value_name = "NAME", conflicts_with = "server_tokens")]
+    pub server_name: Option<String>,
+
+    /// Leave out the Server header, as `--server-tokens off` does
+    #[arg(long, conflicts_with_all = ["server_tokens", "server_name"])]
+    pub no_server_header: bool,
+
+    /// Host serving requests whose Host header is missing or names no known host; by default,
+    /// they are served by the host whose address they were received on
+    #[arg(long, value_name = "HOST")]
+    pub default_host: Option<String>,
+
+    /// File mapping hostnames to directories, one `hostname = /path` per line; when given, hosts
+    /// are taken from it instead of subdirectories. Relative paths are resolved against the
+    /// file's directory
+    #[arg(long, value_name = "PATH", value_parser = Config::read_hosts_file)]
+    pub hosts_file: Option<HostsManifest>,
+
+    /// Refuse to start when the content root of one host lies within another's
+    #[arg(long)]
+    pub isolate_hosts: bool,
+
+    /// Serve given directory under given hostname, as in `example.com=/path/to/dir`.
+    /// Takes precedence over a subdirectory of the same name; may be repeated
+    #[arg(long = "host", value_name = "HOST=DIR", value_parser = Config::parse_host)]
+    pub hosts: Vec<(String, PathBuf)>,
+}
+
+/// Hosts listed in a `--hosts-file`, along with their directories.
+#[derive(Clone)]
+pub struct HostsManifest(pub Vec<(String, PathBuf)>);
+
+/// How worker threads handling connections are allocated.
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum ConcurrencyModel {
+    /// A pool of threads for each listening address
+    PerHost,
+    /// One pool of threads shared by all addresses
+    Shared,
+    /// A single thread handling connections one at a time
+    Single,
+}
+
+/// Format of logs written to standard output.
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable, multi-line entries
+    Pretty,
+    /// One JSON object per line, as in log files
+    Json,
+}
+
+/// Contents of the `Server` response header.
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum ServerTokens {
+    /// Just the server's name
+    Name,
+    /// The name and the version, as in `webserver/1.2.3`
+    Version,
+    /// No Server header at all
+    Off,
 }
 
 impl Config {
+    /// How long an idle connection is kept open; zero disables keep-alive.
+    pub fn keep_alive_timeout(&self) -> Duration {
+        match self.keep_alive_ms {
+            Some(millis) => Duration::from_millis(millis),
+            None => Duration::from_secs(self.keep_alive.into()),
+        }
+    }
+
+    fn verify_path(path: &str) -> Result<PathBuf, String> {
+        match canonicalize(path) {
+            Ok(path) if path.is_file() => match File::open(&path) {
+                Ok(_) => Ok(path),
+                Err(err) => Err(format!("File inaccessible: {}", err)),
+            },
+            _ => Config::verify_dir(path),
+        }
+    }
+
     fn verify_dir(dir: &str) -> Result<PathBuf, String> {
         let path = PathBuf::from(dir);
         match canonicalize(path) {
@@ -88,21 +432,159 @@ impl Config {
             Err(err) => Err(format!("Invalid directory: {}", err)),
         }
     }
+
+    fn read_template(path: &str) -> Result<String, String> {
+        std::fs::read_to_string(path).map_err(|err| format!("Error reading template: {}", err))
+    }
+
+    /// Resolves `--error-page` paths against the content directory, making sure the pages exist.
+    pub fn resolve_error_pages(&mut self) -> Result<(), String> {
+        for (code, path) in &mut self.error_pages {
+            *path = canonicalize(self.directory.join(&*path))
+                .map_err(|err| format!("Invalid error page for {code}: {err}"))?;
+        }
+        Ok(())
+    }
+
+    fn parse_base_path(path: &str) -> Result<String, String> {
+        if !path.starts_with('/') {
+            return Err("Base path must start with '/'".into());
+        }
+        Ok(path.trim_end_matches('/').into())
+    }
+
+    fn parse_error_page(mapping: &str) -> Result<(u16, PathBuf), String> {
+        let Some((code, path)) = mapping.split_once('=') else {
+            return Err("Expected CODE=PATH".into());
+        };
+        match code.parse() {
+            Ok(code @ 100..=599) => Ok((code, path.into())),
+            _ => Err(format!("Invalid status code: {code}")),
+        }
+    }
+
+    fn read_hosts_file(path: &str) -> Result<HostsManifest, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| format!("Error reading hosts file: {}", err))?;
+        let base = Path::new(path).parent().unwrap_or(Path::new(""));
+        let mut hosts = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = line.split_once('=').and_then(|(hostname, dir)| {
+                let hostname = hostname.trim();
+                (!hostname.is_empty()).then(|| (hostname, base.join(dir.trim())))
+            });
+            let Some((hostname, dir)) = entry else {
+                return Err(format!("Line {}: expected `hostname = /path`", number + 1));
+            };
+            let dir = Config::verify_dir(&dir.to_string_lossy())
+                .map_err(|err| format!("Line {}: {}", number + 1, err))?;
+            hosts.push((hostname.into(), dir));
+        }
+        Ok(HostsManifest(hosts))
+    }
+
+    fn parse_header(header: &str) -> Result<(String, String), String> {
+        let Some((name, value)) = header.split_once(':') else {
+            return Err("Expected NAME: VALUE".into());
+        };
+        let is_token = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+        if name.is_empty() || !name.chars().all(is_token) {
+            return Err(format!("Invalid header name: {name}"));
+        }
+        let value = value.trim();
+        if value.contains(['\r', '\n']) {
+            return Err("Header value must not contain line breaks".into());
+        }
+        Ok((name.into(), value.into()))
+    }
+
+    fn parse_mime(mapping: &str) -> Result<(String, mime_guess::Mime), String> {
+        let Some((extension, mime)) = mapping.split_once('=') else {
+            return Err("Expected EXT=TYPE".into());
+        };
+        let extension = extension.trim().trim_start_matches('.');
+        if extension.is_empty() {
+            return Err("Extension must not be empty".into());
+        }
+        let mime = mime
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid content type: {mime}"))?;
+        Ok((extension.to_ascii_lowercase(), mime))
+    }
+
+    fn parse_host(mapping: &str) -> Result<(String, PathBuf), String> {
+        let Some((hostname, dir)) = mapping.split_once('=') else {
+            return Err("Expected HOST=DIR".into());
+        };
+        if hostname.is_empty() {
+            return Err("Hostname must not be empty".into());
+        }
+        // Host headers are matched lowercased, as hostnames are case-insensitive.
+        Ok((hostname.to_ascii_lowercase(), Config::verify_dir(dir)?))
+    }
 }
 
-pub fn get_hosts(config: &Config) -> Vec<DomainHandler> {
-    let mut hostnames = get_hostnames(&config.directory);
+/// Builds the served hosts. Fails if content roots of hosts overlap while `--isolate-hosts` is
+/// given; otherwise overlaps are only warned about.
+pub fn get_hosts(config: &Config) -> Result<Vec<DomainHandler<'_>>, String> {
+    let mut hostnames = if let Some(HostsManifest(hosts)) = &config.hosts_file {
+        hosts
+            .iter()
+            .map(|(hostname, dir)| (dir.clone(), hostname.clone()))
+            .collect()
+    } else if config.directory.is_file() {
+        vec![(config.directory.clone(), "localhost".into())]
+    } else {
+        get_hostnames(&config.directory)
+    };
+    hostnames.retain(|(_, hostname)| config.hosts.iter().all(|(name, _)| name != hostname));
+    hostnames.extend(
+        config
+            .hosts
+            .iter()
+            .map(|(hostname, dir)| (dir.clone(), hostname.clone())),
+    );
+    if let Some((first, second)) = find_overlapping_roots(&hostnames) {
+        let message = format!("Content roots of hosts {first} and {second} overlap");
+        if config.isolate_hosts {
+            return Err(message);
+        }
+        warn!("{message}");
+    }
     let hosts = hostnames.drain(..).map(|(dir, hostname)| {
-        let address: SocketAddr = (hostname.clone(), config.port)
-            .to_socket_addrs()
-            .map_err(|_err| warn!("Invalid IP address for host {}; ignoring", hostname))
-            .ok()?
-            .next()
-            .unwrap();
-        let server_data = static_server::Data::new(dir, config, address, hostname);
+        let mut addresses = config.listen.clone();
+        if let (Some(port), Some(ip)) = (config.port, config.bind) {
+            addresses.push(SocketAddr::new(ip, port));
+        } else if let Some(port) = config.port {
+            match (hostname.as_str(), port).to_socket_addrs() {
+                Ok(mut resolved) => addresses.extend(resolved.next()),
+                Err(_) if addresses.is_empty() => {
+                    warn!("Invalid IP address for host {}; ignoring", hostname);
+                    return None;
+                }
+                Err(_) => warn!("Invalid IP address for host {}", hostname),
+            }
+        }
+        let server_data = static_server::Data::new(dir, config, addresses, hostname);
         Some(DomainHandler::StaticDir(server_data))
     });
-    hosts.flatten().collect()
+    Ok(hosts.flatten().collect())
+}
+
+/// Finds two hosts one of whose content roots contains the other's, letting a host serve files
+/// of another one. Roots are canonical, so symlinks are resolved already.
+fn find_overlapping_roots(hosts: &[(PathBuf, String)]) -> Option<(&str, &str)> {
+    hosts.iter().enumerate().find_map(|(i, (dir, hostname))| {
+        hosts[i + 1..]
+            .iter()
+            .find(|(other, _)| dir.starts_with(other) || other.starts_with(dir))
+            .map(|(_, other)| (hostname.as_str(), other.as_str()))
+    })
 }
 
 fn get_hostnames(root: &Path) -> Vec<(PathBuf, String)> {
@@ -127,3 +609,33 @@ fn get_hostnames(root: &Path) -> Vec<(PathBuf, String)> {
     }
     hosts
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roots(hosts: &[(&str, &str)]) -> Vec<(PathBuf, String)> {
+        hosts
+            .iter()
+            .map(|(dir, hostname)| (dir.into(), (*hostname).into()))
+            .collect()
+    }
+
+    #[test]
+    fn nested_roots_overlap_either_way() {
+        let hosts = roots(&[("/srv/a", "a"), ("/srv/b", "b"), ("/srv/a/sub", "sub")]);
+        assert_eq!(find_overlapping_roots(&hosts), Some(("a", "sub")));
+        let hosts = roots(&[("/srv/a/sub", "sub"), ("/srv/a", "a")]);
+        assert_eq!(find_overlapping_roots(&hosts), Some(("sub", "a")));
+        let hosts = roots(&[("/srv/a", "a"), ("/srv/a", "again")]);
+        assert_eq!(find_overlapping_roots(&hosts), Some(("a", "again")));
+    }
+
+    #[test]
+    fn sibling_roots_dont_overlap() {
+        // Paths are compared by component, so a shared name prefix is no overlap.
+        let hosts = roots(&[("/srv/a", "a"), ("/srv/ab", "ab"), ("/srv/b", "b")]);
+        assert_eq!(find_overlapping_roots(&hosts), None);
+        assert_eq!(find_overlapping_roots(&[]), None);
+    }
+}
@@ -1,39 +1,275 @@
-use std::{fs, time::SystemTime};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
 
-use time::{macros::format_description, OffsetDateTime};
-use tracing::subscriber;
+use time::{macros::format_description, Date, Month, OffsetDateTime, UtcOffset};
+use tracing::{subscriber, warn};
 use tracing_subscriber::{
+    filter::LevelFilter,
     fmt::{layer, time as fmt_time},
     layer::SubscriberExt,
-    registry,
+    registry, Layer,
 };
 
-pub fn init() {
-    let today: OffsetDateTime = SystemTime::now().into();
-    let log_file_path = format!("logs/{}.log.json", today.date());
-    fs::create_dir_all("logs").expect("Failed to create logs directory");
-    let log_file = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(log_file_path)
-        .unwrap();
+use crate::{Config, LogFormat};
+
+/// Installs the global logger, writing to standard output in the configured format and at the
+/// configured level and, unless disabled, to rotated JSON files in the log directory, in full.
+/// When the file can't be opened, logs only go to standard output.
+pub fn init(config: &Config) -> Result<(), subscriber::SetGlobalDefaultError> {
+    // Local time may be unknown, e.g. in containers without `/etc/localtime`.
+    let offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
+
+    let (log_file, open_error, prune_error) = if config.no_log_file {
+        (None, None, None)
+    } else {
+        let dir = &config.log_dir;
+        match RotatingFile::open(dir, config.log_max_size, config.log_keep, offset) {
+            Ok(file) => {
+                let pruned = file.prune();
+                (Some(file), None, pruned.err())
+            }
+            Err(err) => (None, Some(err), None),
+        }
+    };
+    let stdout_level = stdout_level(config);
+    let pretty_logger = matches!(config.log_format, LogFormat::Pretty).then(|| {
+        layer()
+            .pretty()
+            .with_timer(fmt_time::OffsetTime::new(
+                offset,
+                format_description!("[hour]:[minute]:[second]:[subsecond digits:4]"),
+            ))
+            .with_file(false)
+            .with_line_number(false)
+            .with_filter(stdout_level)
+    });
 
-    let offset = time::UtcOffset::current_local_offset().unwrap();
-    let pretty_logger = layer()
-        .pretty()
-        .with_timer(fmt_time::OffsetTime::new(
+    let json_stdout_logger = matches!(config.log_format, LogFormat::Json).then(|| {
+        layer()
+            .json()
+            .with_thread_names(true)
+            .with_file(true)
+            .with_filter(stdout_level)
+    });
+
+    let json_logger = log_file.map(|log_file| {
+        layer()
+            .json()
+            .with_writer(Mutex::new(log_file))
+            .with_thread_names(true)
+            .with_file(true)
+    });
+
+    let logger = registry()
+        .with(pretty_logger)
+        .with(json_stdout_logger)
+        .with(json_logger);
+    subscriber::set_global_default(logger)?;
+
+    if let Some(err) = open_error {
+        warn!(
+            "Failed to open log file in {}, logging to standard output only: {err}",
+            config.log_dir.display()
+        );
+    }
+    if let Some(err) = prune_error {
+        warn!("Failed to delete old log files: {err}");
+    }
+    if let Ok(directives) = std::env::var("RUST_LOG") {
+        if !directives.trim().is_empty() && LevelFilter::from_str(directives.trim()).is_err() {
+            warn!("Ignoring RUST_LOG={directives:?}, which is not a plain level such as `debug`");
+        }
+    }
+    Ok(())
+}
+
+/// Most detailed level logged to standard output: the one in `RUST_LOG`, if it holds a plain
+/// level such as `debug`, or else `info` raised by `--verbose` or lowered by `--quiet`.
+pub fn stdout_level(config: &Config) -> LevelFilter {
+    const LEVELS: [LevelFilter; 6] = [
+        LevelFilter::OFF,
+        LevelFilter::ERROR,
+        LevelFilter::WARN,
+        LevelFilter::INFO,
+        LevelFilter::DEBUG,
+        LevelFilter::TRACE,
+    ];
+    if let Some(level) = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|level| LevelFilter::from_str(level.trim()).ok())
+    {
+        return level;
+    }
+    let index = (3 + usize::from(config.verbose)).saturating_sub(usize::from(config.quiet));
+    LEVELS[index.min(LEVELS.len() - 1)]
+}
+
+/// Log file named after the current local date, replaced by a new one at midnight and whenever
+/// it would grow over `max_size`. Only the `keep` most recent files remain in the directory.
+///
+/// Later files of a day are numbered, as in `2024-01-31.log.json`, `2024-01-31.1.log.json`.
+pub struct RotatingFile {
+    dir: PathBuf,
+    max_size: u64,
+    keep: usize,
+    /// Offset of local time, which can't be determined reliably once threads are spawned.
+    offset: UtcOffset,
+    /// Source of the current time, which tests may set.
+    clock: fn() -> OffsetDateTime,
+    file: File,
+    date: Date,
+    index: u32,
+    size: u64,
+}
+
+impl RotatingFile {
+    /// Opens the latest file of today for appending. Old files are left until the next rotation
+    /// or `prune`.
+    pub fn open(
+        dir: &Path,
+        max_size: u64,
+        keep: usize,
+        offset: UtcOffset,
+    ) -> io::Result<RotatingFile> {
+        RotatingFile::open_with_clock(dir, max_size, keep, offset, OffsetDateTime::now_utc)
+    }
+
+    /// Opens the latest file of the day `clock` tells for appending, as `open` does with the
+    /// system clock.
+    pub fn open_with_clock(
+        dir: &Path,
+        max_size: u64,
+        keep: usize,
+        offset: UtcOffset,
+        clock: fn() -> OffsetDateTime,
+    ) -> io::Result<RotatingFile> {
+        fs::create_dir_all(dir)?;
+        let date = clock().to_offset(offset).date();
+        let index = latest_index(dir, date)?;
+        let file = open_log_file(dir, date, index)?;
+        Ok(RotatingFile {
+            dir: dir.to_owned(),
+            max_size,
+            keep,
             offset,
-            format_description!("[hour]:[minute]:[second]:[subsecond digits:4]"),
-        ))
-        .with_file(false)
-        .with_line_number(false);
-
-    let json_logger = layer()
-        .json()
-        .with_writer(log_file)
-        .with_thread_names(true)
-        .with_file(true);
-
-    let logger = registry().with(pretty_logger).with(json_logger);
-    subscriber::set_global_default(logger).expect("Failed to set global logger.");
+            clock,
+            size: file.metadata()?.len(),
+            file,
+            date,
+            index,
+        })
+    }
+
+    fn today(&self) -> Date {
+        (self.clock)().to_offset(self.offset).date()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let date = self.today();
+        let index = if date == self.date { self.index + 1 } else { 0 };
+        self.file = open_log_file(&self.dir, date, index)?;
+        self.size = self.file.metadata()?.len();
+        self.date = date;
+        self.index = index;
+        self.prune()
+    }
+
+    /// Deletes all but the current and the `keep - 1` most recently modified other log files,
+    /// leaving files not named like log files alone. Deleting goes on past failures, the first of
+    /// which is returned.
+    pub fn prune(&self) -> io::Result<()> {
+        if self.keep == 0 {
+            return Ok(());
+        }
+        let current = log_file_name(self.date, self.index);
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let Some(name) = name.to_str().filter(|name| *name != current) else {
+                continue;
+            };
+            if parse_log_file_name(name).is_none() {
+                continue;
+            }
+            // A file deleted meanwhile needs no deleting.
+            if let Ok(modified) = entry.metadata().and_then(|metadata| metadata.modified()) {
+                files.push((modified, entry.path()));
+            }
+        }
+        files.sort();
+        let stale = files.len().saturating_sub(self.keep - 1);
+        let mut result = Ok(());
+        for (_, path) in &files[..stale] {
+            if let Err(err) = fs::remove_file(path) {
+                result = result.and(Err(err));
+            }
+        }
+        result
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let full = self.max_size != 0 && self.size + buf.len() as u64 > self.max_size;
+        if (full && self.size > 0) || self.today() != self.date {
+            // Until a new file can be opened, writing goes on to the current one.
+            let _ = self.rotate();
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn log_file_name(date: Date, index: u32) -> String {
+    match index {
+        0 => format!("{date}.log.json"),
+        index => format!("{date}.{index}.log.json"),
+    }
+}
+
+/// Date and index of a file named by `log_file_name`, or `None` for any other name.
+fn parse_log_file_name(name: &str) -> Option<(Date, u32)> {
+    let stem = name.strip_suffix(".log.json")?;
+    let (date, index) = match stem.split_once('.') {
+        // The first file of a day has no index, so no other one is 0.
+        Some((date, index)) => (date, index.parse().ok().filter(|&index| index > 0)?),
+        None => (stem, 0),
+    };
+    let mut parts = date.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = Month::try_from(parts.next()?.parse::<u8>().ok()?).ok()?;
+    let day = parts.next()?.parse().ok()?;
+    let parsed = Date::from_calendar_date(year, month, day).ok()?;
+    // Only the exact form written, e.g. not `2024-1-31`, names a log file.
+    (log_file_name(parsed, index) == name).then_some((parsed, index))
+}
+
+/// Index of the latest log file of the day in the directory, or 0 if there is none.
+fn latest_index(dir: &Path, date: Date) -> io::Result<u32> {
+    let mut latest = 0;
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        if let Some((file_date, index)) = name.to_str().and_then(parse_log_file_name) {
+            if file_date == date {
+                latest = latest.max(index);
+            }
+        }
+    }
+    Ok(latest)
+}
+
+fn open_log_file(dir: &Path, date: Date, index: u32) -> io::Result<File> {
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(log_file_name(date, index)))
 }
@@ -0,0 +1,172 @@
+//! Process-wide counters, rendered in Prometheus text format.
+//!
+//! Requests to the metrics endpoint itself are counted like any other request.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+static RESPONSES_BY_CLASS: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+static BYTES_SENT: AtomicU64 = AtomicU64::new(0);
+static ACTIVE_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+static CONNECTIONS_CLOSED: AtomicU64 = AtomicU64::new(0);
+static CONNECTIONS_REUSED: AtomicU64 = AtomicU64::new(0);
+static CONNECTION_REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+static HOSTS: Mutex<Vec<(String, Arc<HostTraffic>)>> = Mutex::new(Vec::new());
+
+/// Bytes exchanged with clients on behalf of a single host.
+#[derive(Default)]
+pub struct HostTraffic {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl HostTraffic {
+    /// Records a request of `received` bytes answered with a response of `sent` bytes.
+    pub fn record(&self, received: usize, sent: usize) {
+        self.received.fetch_add(received as u64, Ordering::Relaxed);
+        self.sent.fetch_add(sent as u64, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+}
+
+/// Gets the traffic counters of a host, which are then included in rendered metrics. A host
+/// registered again, as by another server in the process, shares its counters.
+pub fn register_host(hostname: &str) -> Arc<HostTraffic> {
+    let mut hosts = HOSTS.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some((_, traffic)) = hosts.iter().find(|(name, _)| name == hostname) {
+        return Arc::clone(traffic);
+    }
+    let traffic = Arc::new(HostTraffic::default());
+    hosts.push((hostname.to_string(), Arc::clone(&traffic)));
+    traffic
+}
+
+pub fn connection_opened() {
+    ACTIVE_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records a closed connection along with the number of requests it served.
+pub fn connection_closed(requests: u64) {
+    ACTIVE_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+    CONNECTIONS_CLOSED.fetch_add(1, Ordering::Relaxed);
+    CONNECTION_REQUESTS.fetch_add(requests, Ordering::Relaxed);
+    if requests > 1 {
+        CONNECTIONS_REUSED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn request_received() {
+    REQUESTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records a response written to a client, `bytes` counting the whole rendered message.
+pub fn response_sent(status_code: u16, bytes: usize) {
+    let class = usize::from(status_code / 100).clamp(1, 5) - 1;
+    RESPONSES_BY_CLASS[class].fetch_add(1, Ordering::Relaxed);
+    BYTES_SENT.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// Renders all metrics in Prometheus text format.
+pub fn render() -> String {
+    let mut out = String::new();
+    let load = |metric: &AtomicU64| metric.load(Ordering::Relaxed);
+
+    write_metric(&mut out, "webserver_requests_total", "Requests received", "counter");
+    writeln!(out, "webserver_requests_total {}", load(&REQUESTS)).unwrap();
+
+    write_metric(
+        &mut out,
+        "webserver_responses_total",
+        "Responses sent, by status class",
+        "counter",
+    );
+    for (class, counter) in RESPONSES_BY_CLASS.iter().enumerate() {
+        let class = class + 1;
+        let value = load(counter);
+        writeln!(out, "webserver_responses_total{{class=\"{class}xx\"}} {value}").unwrap();
+    }
+
+    let simple_metrics = [
+        (
+            "webserver_sent_bytes_total",
+            "Bytes of responses sent, headers included",
+            "counter",
+            &BYTES_SENT,
+        ),
+        (
+            "webserver_active_connections",
+            "Connections currently open",
+            "gauge",
+            &ACTIVE_CONNECTIONS,
+        ),
+        (
+            "webserver_connections_closed_total",
+            "Connections closed so far",
+            "counter",
+            &CONNECTIONS_CLOSED,
+        ),
+        (
+            "webserver_connections_reused_total",
+            "Closed connections which served more than one request",
+            "counter",
+            &CONNECTIONS_REUSED,
+        ),
+        (
+            "webserver_connection_requests_total",
+            "Requests served by closed connections",
+            "counter",
+            &CONNECTION_REQUESTS,
+        ),
+    ];
+    for (name, help, kind, metric) in simple_metrics {
+        write_metric(&mut out, name, help, kind);
+        writeln!(out, "{name} {}", load(metric)).unwrap();
+    }
+
+    let hosts = HOSTS.lock().unwrap_or_else(|err| err.into_inner());
+    write_metric(
+        &mut out,
+        "webserver_host_sent_bytes_total",
+        "Bytes of responses sent, by host",
+        "counter",
+    );
+    for (host, traffic) in hosts.iter() {
+        let host = escape_label(host);
+        let value = traffic.sent();
+        writeln!(out, "webserver_host_sent_bytes_total{{host=\"{host}\"}} {value}").unwrap();
+    }
+    write_metric(
+        &mut out,
+        "webserver_host_received_bytes_total",
+        "Bytes of requests received, by host",
+        "counter",
+    );
+    for (host, traffic) in hosts.iter() {
+        let host = escape_label(host);
+        let value = traffic.received();
+        writeln!(out, "webserver_host_received_bytes_total{{host=\"{host}\"}} {value}").unwrap();
+    }
+    out
+}
+
+fn write_metric(out: &mut String, name: &str, help: &str, kind: &str) {
+    writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}").unwrap();
+}
+
+/// Escapes a label value as the text format requires.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
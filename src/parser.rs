@@ -0,0 +1,59 @@
+//! Parsing of request heads, independent of how their bytes are received.
+
+use tracing::{debug, warn};
+
+use crate::http::Request;
+
+/// How much of a malformed request is logged, in bytes.
+const LOGGED_INPUT_LIMIT: usize = 256;
+
+pub enum Error {
+    /// The head has not been received in full yet.
+    Partial,
+    /// The head has more headers than room was made for.
+    TooManyHeaders,
+    Syntax,
+    /// The head declares different lengths of the body.
+    ConflictingLength,
+}
+
+/// Parses the head of a request at the start of the buffer, making room for up to `headers_size`
+/// headers. Returns the request and the size of its head.
+pub fn try_parse(headers_size: usize, buffer: &[u8]) -> Result<(Request, usize), Error> {
+    let mut headers = vec![httparse::EMPTY_HEADER; headers_size];
+    let mut req = httparse::Request::new(&mut headers);
+    match req.parse(buffer) {
+        Ok(httparse::Status::Complete(_)) if has_conflicting_lengths(req.headers) => {
+            Err(Error::ConflictingLength)
+        }
+        Ok(httparse::Status::Complete(s)) => match Request::new(req) {
+            Some(request) => Ok((request, s)),
+            None => Err(Error::Syntax),
+        },
+        Ok(httparse::Status::Partial) => Err(Error::Partial),
+        Err(httparse::Error::TooManyHeaders) => Err(Error::TooManyHeaders),
+        Err(err) => {
+            warn!("Parsing error: {}", err);
+            let snippet = &buffer[..buffer.len().min(LOGGED_INPUT_LIMIT)];
+            debug!(
+                kind = ?err,
+                input = %snippet.escape_ascii(),
+                truncated = buffer.len() > LOGGED_INPUT_LIMIT,
+                "Malformed request"
+            );
+            Err(Error::Syntax)
+        }
+    }
+}
+
+/// Whether the headers declare several different lengths of the body; as either could be taken
+/// as the right one, such a request can't be framed safely. Repeating the same length is fine.
+fn has_conflicting_lengths(headers: &[httparse::Header]) -> bool {
+    let mut lengths = headers
+        .iter()
+        .filter(|header| header.name.eq_ignore_ascii_case("Content-Length"))
+        .map(|header| header.value.trim_ascii());
+    lengths
+        .next()
+        .is_some_and(|first| lengths.any(|length| length != first))
+}
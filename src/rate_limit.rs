@@ -0,0 +1,67 @@
+//! Per-client limit on the rate of requests, enforced with token buckets.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often buckets of clients gone quiet are dropped.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Token buckets of the clients of one server.
+pub struct RateLimiter {
+    state: Mutex<State>,
+    /// Requests allowed per second, which is also the size of a burst.
+    rate: f64,
+}
+
+struct State {
+    buckets: HashMap<IpAddr, Bucket>,
+    pruned: Instant,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Limits each client to `rate` requests per second, allowing bursts of as many.
+    pub fn new(rate: u32) -> RateLimiter {
+        RateLimiter {
+            state: Mutex::new(State {
+                buckets: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+            rate: rate.into(),
+        }
+    }
+
+    /// Takes a token for a request of the client. When none is left, returns how long until the
+    /// next one.
+    pub fn acquire(&self, client: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        if now.duration_since(state.pruned) >= PRUNE_INTERVAL {
+            // Buckets refilled by now are no different from new ones.
+            let refill = Duration::from_secs(1);
+            state
+                .buckets
+                .retain(|_, bucket| now.duration_since(bucket.updated) < refill);
+            state.pruned = now;
+        }
+        let bucket = state.buckets.entry(client).or_insert(Bucket {
+            tokens: self.rate,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+}
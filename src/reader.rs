@@ -1,24 +1,397 @@
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 use std::net::TcpStream;
-use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use tracing::warn;
 
-use crate::{http::Request, Config};
+use crate::http::{Request, Status};
+use crate::parser::{self, try_parse};
+use crate::Config;
+
+/// Idle timeout used when keep-alive is disabled, as the one request must still arrive.
+const NO_KEEP_ALIVE_READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Longest line of chunked body framing accepted, in bytes.
+const MAX_CHUNK_LINE: usize = 4096;
+
+/// Bytes held by all request buffers, checked against `--max-buffer-memory`.
+static BUFFERED_BYTES: AtomicUsize = AtomicUsize::new(0);
 
 pub enum ReadError {
     ConnectionClosed,
     Timeout,
     BadSyntax(Option<String>),
     TooManyHeaders,
+    /// The head of the request is larger than `--max-header-bytes`.
+    HeadTooLarge,
+    /// The body is sent with a transfer coding other than chunked.
+    UnsupportedCoding,
+    /// Reading the request would exceed the server-wide buffer memory budget.
+    OutOfMemory,
 }
 
-pub fn read_request(stream: &mut TcpStream, config: &Config) -> Result<Request, ReadError> {
+impl From<parser::Error> for ReadError {
+    fn from(err: parser::Error) -> ReadError {
+        match err {
+            // A request cut short for good is as malformed as any, but told apart from one
+            // which may be a simple request.
+            parser::Error::Partial => {
+                ReadError::BadSyntax(Some("Request head is incomplete.".into()))
+            }
+            parser::Error::Syntax => ReadError::BadSyntax(None),
+            parser::Error::TooManyHeaders => ReadError::TooManyHeaders,
+            parser::Error::ConflictingLength => ReadError::BadSyntax(Some(
+                "Content-Length must not be given different values.".into(),
+            )),
+        }
+    }
+}
+
+/// Bytes received on a connection but not consumed yet. Their size is accounted for server-wide,
+/// until they are consumed or the buffer is dropped.
+#[derive(Default)]
+pub struct RequestBuffer {
+    data: Vec<u8>,
+}
+
+impl RequestBuffer {
+    pub fn new() -> RequestBuffer {
+        RequestBuffer::default()
+    }
+
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Appends the bytes, unless all buffers would hold more than `budget` bytes then; a zero
+    /// budget is unlimited.
+    fn extend(&mut self, bytes: &[u8], budget: usize) -> Result<(), ReadError> {
+        let total = BUFFERED_BYTES.fetch_add(bytes.len(), Ordering::Relaxed) + bytes.len();
+        if budget != 0 && total > budget {
+            BUFFERED_BYTES.fetch_sub(bytes.len(), Ordering::Relaxed);
+            return Err(ReadError::OutOfMemory);
+        }
+        self.data.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn consume(&mut self, count: usize) {
+        self.data.drain(..count);
+        BUFFERED_BYTES.fetch_sub(count, Ordering::Relaxed);
+    }
+}
+
+impl Drop for RequestBuffer {
+    fn drop(&mut self) {
+        BUFFERED_BYTES.fetch_sub(self.data.len(), Ordering::Relaxed);
+    }
+}
+
+/// Reads the head of a single request from the connection. Returns the request and its body,
+/// which is read from the connection as it is consumed.
+///
+/// `buffer` holds bytes received but not consumed yet; whatever follows the request (e.g. a
+/// pipelined one) is left in it for the next call, once the body has been consumed.
+pub fn read_request<'a>(
+    stream: &'a mut TcpStream,
+    config: &'a Config,
+    buffer: &'a mut RequestBuffer,
+) -> Result<(Request, Body<'a>), ReadError> {
+    // A pipelined request has already started arriving.
+    let mut started = (buffer.len() > 0).then(Instant::now);
+    let (mut request, body_start) = loop {
+        let result = match try_read(&buffer.data, config.max_headers_number) {
+            ReadResult::Err(ReadError::BadSyntax(None)) if config.allow_http09 => {
+                try_read_simple(&buffer.data)
+            }
+            result => result,
+        };
+        let too_large = |size| config.max_header_bytes != 0 && size > config.max_header_bytes;
+        match result {
+            ReadResult::Partial if too_large(buffer.len()) => return Err(ReadError::HeadTooLarge),
+            ReadResult::Ok((_, head_size)) if too_large(head_size) => {
+                return Err(ReadError::HeadTooLarge)
+            }
+            ReadResult::Partial => {
+                let limit = match (config.header_timeout, config.request_timeout) {
+                    (0, request) => request,
+                    (header, 0) => header,
+                    (header, request) => header.min(request),
+                };
+                read_more(stream, buffer, config, &mut started, limit)?;
+            }
+            ReadResult::Err(err) => return Err(err),
+            ReadResult::Ok(res) => break res,
+        }
+    };
+    normalize_target(&mut request)?;
+
+    let chunked = is_chunked(&request)?;
+    let length = if chunked {
+        0
+    } else {
+        get_content_length(&request)?
+    };
+    buffer.consume(body_start);
+    let body = Body {
+        continue_pending: (chunked || length > buffer.len() as u64) && expects_continue(&request),
+        stream,
+        config,
+        buffer,
+        started,
+        remaining: length,
+        chunks: if chunked { Chunks::First } else { Chunks::None },
+        received: body_start,
+    };
+    Ok((request, body))
+}
+
+/// The body of a request, pulled from the connection on demand so it is never held in memory as
+/// a whole.
+pub struct Body<'a> {
+    stream: &'a mut TcpStream,
+    config: &'a Config,
+    buffer: &'a mut RequestBuffer,
+    started: Option<Instant>,
+    /// Size of the rest of the body, or of the current chunk of a chunked one.
+    remaining: u64,
+    chunks: Chunks,
+    /// Size of the request received so far, head included.
+    received: usize,
+    /// Whether the client waits for `100 Continue` before sending the body.
+    continue_pending: bool,
+}
+
+impl Body<'_> {
+    /// Size of the request received so far, in bytes, including its head.
+    pub fn received(&self) -> usize {
+        self.received
+    }
+
+    /// Reads and drops whatever remains of the body, so the next request can be read.
+    pub fn discard(&mut self) -> Result<(), ReadError> {
+        while self.has_more()? {
+            self.fill()?;
+            self.advance(self.buffered());
+        }
+        Ok(())
+    }
+
+    /// Whether any of the body is left, reading the size of the next chunk if needed.
+    fn has_more(&mut self) -> Result<bool, ReadError> {
+        if self.remaining == 0 && matches!(self.chunks, Chunks::First | Chunks::Next) {
+            self.next_chunk()?;
+        }
+        Ok(self.remaining > 0)
+    }
+
+    /// Reads the line break ending the previous chunk, if any, and the size of the next one.
+    /// The last chunk is empty and followed by trailers, which are dropped.
+    fn next_chunk(&mut self) -> Result<(), ReadError> {
+        if self.chunks == Chunks::Next && !self.read_line()?.is_empty() {
+            return Err(malformed_chunk());
+        }
+        let line = self.read_line()?;
+        // Chunk extensions follow the size, and are ignored.
+        let size = line.split(|&b| b == b';').next().unwrap_or_default();
+        let size = std::str::from_utf8(size)
+            .ok()
+            .map(str::trim)
+            .filter(|size| !size.is_empty() && size.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|size| u64::from_str_radix(size, 16).ok())
+            .ok_or_else(malformed_chunk)?;
+        if size == 0 {
+            while !self.read_line()?.is_empty() {}
+            self.chunks = Chunks::Done;
+        } else {
+            self.chunks = Chunks::Next;
+        }
+        self.remaining = size;
+        Ok(())
+    }
+
+    /// Reads a line of chunked framing, without its line break.
+    fn read_line(&mut self) -> Result<Vec<u8>, ReadError> {
+        loop {
+            if let Some(end) = self.buffer.data.iter().position(|&b| b == b'\n') {
+                let line = &self.buffer.data[..end];
+                let line = line.strip_suffix(b"\r").unwrap_or(line).to_vec();
+                self.buffer.consume(end + 1);
+                self.received += end + 1;
+                return Ok(line);
+            }
+            if self.buffer.len() > MAX_CHUNK_LINE {
+                return Err(malformed_chunk());
+            }
+            self.receive()?;
+        }
+    }
+
+    /// Makes sure some of the body is buffered, reading it from the connection if needed.
+    fn fill(&mut self) -> Result<(), ReadError> {
+        if self.buffer.len() > 0 {
+            return Ok(());
+        }
+        self.receive()
+    }
+
+    /// Reads more from the connection, first letting the client know it may send the body.
+    fn receive(&mut self) -> Result<(), ReadError> {
+        if self.continue_pending {
+            self.continue_pending = false;
+            let interim = format!("HTTP/1.1 {}\r\n\r\n", Status::Continue.code());
+            self.stream
+                .write_all(interim.as_bytes())
+                .map_err(|_| ReadError::ConnectionClosed)?;
+        }
+        let limit = self.config.request_timeout;
+        read_more(
+            self.stream,
+            self.buffer,
+            self.config,
+            &mut self.started,
+            limit,
+        )
+    }
+
+    /// How much of the buffer belongs to the body.
+    fn buffered(&self) -> usize {
+        usize::try_from(self.remaining)
+            .map_or(self.buffer.len(), |rest| rest.min(self.buffer.len()))
+    }
+
+    fn advance(&mut self, count: usize) {
+        self.buffer.consume(count);
+        self.remaining -= count as u64;
+        self.received += count;
+    }
+}
+
+impl Read for Body<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let to_io_error = |err| {
+            let kind = match err {
+                ReadError::Timeout => io::ErrorKind::TimedOut,
+                ReadError::OutOfMemory => io::ErrorKind::OutOfMemory,
+                ReadError::BadSyntax(_) => io::ErrorKind::InvalidData,
+                _ => io::ErrorKind::UnexpectedEof,
+            };
+            io::Error::from(kind)
+        };
+        if buf.is_empty() || !self.has_more().map_err(to_io_error)? {
+            return Ok(0);
+        }
+        self.fill().map_err(to_io_error)?;
+        let count = buf.len().min(self.buffered());
+        buf[..count].copy_from_slice(&self.buffer.data[..count]);
+        self.advance(count);
+        Ok(count)
+    }
+}
+
+/// Brings the request target to the origin form handlers deal with, as in `/path?query`. The
+/// authority of an absolute-form target, as in `http://example.com/path`, replaces the Host
+/// header. The asterisk form is left as is for OPTIONS.
+fn normalize_target(request: &mut Request) -> Result<(), ReadError> {
+    if request.path == "*" && request.method == "OPTIONS" {
+        return Ok(());
+    }
+    if let Some((authority, path)) = split_absolute_form(&request.path) {
+        request
+            .headers
+            .insert("host".into(), vec![authority.into()]);
+        request.path = path;
+    }
+    // This also keeps empty targets away from handlers.
+    if !request.path.starts_with('/') {
+        return Err(ReadError::BadSyntax(Some(
+            "Request target must start with '/'.".into(),
+        )));
+    }
+    Ok(())
+}
+
+/// Splits an absolute-form HTTP target into its authority and its path, which may be empty.
+fn split_absolute_form(target: &str) -> Option<(String, String)> {
+    let (scheme, rest) = target.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+    let (authority, path) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+    let path = if path.starts_with('/') {
+        path.into()
+    } else {
+        format!("/{path}")
+    };
+    Some((authority.into(), path))
+}
+
+/// How far reading a chunked body has got.
+#[derive(Clone, Copy, PartialEq)]
+enum Chunks {
+    /// The body is not chunked, but of a declared length.
+    None,
+    /// The size of the first chunk comes next.
+    First,
+    /// The rest of the current chunk comes next, then the size of another one.
+    Next,
+    /// The last chunk and its trailers have been read.
+    Done,
+}
+
+fn malformed_chunk() -> ReadError {
+    ReadError::BadSyntax(Some("Malformed chunked body.".into()))
+}
+
+fn expects_continue(req: &Request) -> bool {
+    req.version == 1
+        && req
+            .header("Expect")
+            .is_some_and(|v| v.eq_ignore_ascii_case(b"100-continue"))
+}
+
+/// Reads more of the request. Until its first bytes arrive, the connection is idle: running out
+/// of the keep-alive timeout then closes it quietly. Afterwards, receiving the request may take
+/// up to `limit` seconds since they arrived (0 means no limit), and running out of it is a
+/// `Timeout`.
+fn read_more(
+    stream: &mut TcpStream,
+    buffer: &mut RequestBuffer,
+    config: &Config,
+    started: &mut Option<Instant>,
+    limit: u64,
+) -> Result<(), ReadError> {
+    let timeout = match started {
+        None => match config.keep_alive_timeout() {
+            timeout if timeout.is_zero() => Some(NO_KEEP_ALIVE_READ_TIMEOUT),
+            timeout => Some(timeout),
+        },
+        Some(_) if limit == 0 => None,
+        Some(started) => {
+            let limit = Duration::from_secs(limit);
+            match limit.checked_sub(started.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => Some(remaining),
+                _ => return Err(ReadError::Timeout),
+            }
+        }
+    };
+    stream.set_read_timeout(timeout).unwrap();
+    match fill_buffer(stream, buffer, config.max_buffer_memory) {
+        Err(ReadError::Timeout) if started.is_none() => Err(ReadError::ConnectionClosed),
+        result => {
+            started.get_or_insert_with(Instant::now);
+            result
+        }
+    }
+}
+
+fn fill_buffer(
+    stream: &mut TcpStream,
+    buffer: &mut RequestBuffer,
+    budget: usize,
+) -> Result<(), ReadError> {
     let mut read_buf = [0; 1024];
-    let mut buffer = Vec::with_capacity(1024);
-    stream
-        .set_read_timeout(Some(Duration::new(config.keep_alive.into(), 0)))
-        .unwrap();
     loop {
         match stream.read(&mut read_buf) {
             Ok(0) => {
@@ -29,24 +402,13 @@ pub fn read_request(stream: &mut TcpStream, config: &Config) -> Result<Request,
                 {
                     break Err(ReadError::Timeout);
                 } // 408
-                warn!("err: {}", err.kind());
+                if err.kind() != io::ErrorKind::Interrupted {
+                    warn!("err: {}", err.kind());
+                    break Err(ReadError::ConnectionClosed);
+                }
             }
             Ok(bytes_read) => {
-                buffer.extend_from_slice(&read_buf[..bytes_read]);
-                match try_read(&mut buffer, config.max_headers_number) {
-                    ReadResult::Partial => continue,
-                    ReadResult::Err(err) => break Err(err),
-                    ReadResult::Ok(res) => {
-                        let res = if res.path.starts_with('/') {
-                            Ok(res)
-                        } else {
-                            Err(ReadError::BadSyntax(Some(
-                                "Request target must start with '/'.".into(),
-                            )))
-                        };
-                        break res;
-                    }
-                }
+                break buffer.extend(&read_buf[..bytes_read], budget);
             }
         }
     }
@@ -54,64 +416,77 @@ pub fn read_request(stream: &mut TcpStream, config: &Config) -> Result<Request,
 
 enum ReadResult {
     Partial,
-    Ok(Request),
+    Ok((Request, usize)),
     Err(ReadError),
 }
 
-fn try_read(buffer: &mut [u8], max_headers_count: usize) -> ReadResult {
+fn try_read(buffer: &[u8], max_headers_count: usize) -> ReadResult {
     let mut headers_size = 16;
     loop {
         match try_parse(headers_size, buffer) {
-            Err(ParsingError::Partial) => break ReadResult::Partial,
-            Err(ParsingError::TooManyHeaders) => {
-                if headers_size < max_headers_count {
-                    headers_size = usize::min(2 * headers_size, max_headers_count);
-                } else {
-                    break ReadResult::Err(ReadError::TooManyHeaders);
-                }
-            }
-            Err(ParsingError::Syntax) => break ReadResult::Err(ReadError::BadSyntax(None)),
-            Ok((req, _s)) => {
-                if let Err(err) = get_content_length(&req) {
-                    break err;
-                }
-                break ReadResult::Ok(req);
+            Err(parser::Error::Partial) => break ReadResult::Partial,
+            Err(parser::Error::TooManyHeaders) if headers_size < max_headers_count => {
+                headers_size = usize::min(2 * headers_size, max_headers_count);
             }
+            Err(err) => break ReadResult::Err(err.into()),
+            Ok(res) => break ReadResult::Ok(res),
         }
     }
 }
 
-enum ParsingError {
-    Partial,
-    TooManyHeaders,
-    Syntax,
+/// Reads an HTTP/0.9 simple request: a request line with no version and no headers following.
+fn try_read_simple(buffer: &[u8]) -> ReadResult {
+    let Some(end) = buffer.iter().position(|&b| b == b'\n') else {
+        return ReadResult::Partial;
+    };
+    let line = String::from_utf8_lossy(&buffer[..end]);
+    match line.split_ascii_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", path] => ReadResult::Ok((Request::simple(path.into()), end + 1)),
+        _ => ReadResult::Err(ReadError::BadSyntax(None)),
+    }
 }
 
-fn try_parse(headers_size: usize, buffer: &mut [u8]) -> Result<(Request, usize), ParsingError> {
-    let mut headers = vec![httparse::EMPTY_HEADER; headers_size];
-    let mut req = httparse::Request::new(&mut headers);
-    match req.parse(buffer) {
-        Ok(httparse::Status::Complete(s)) => {
-            // let a:Vec<u8> = buffer.into_iter().skip(s).collect();
-            Ok((Request::new(req), s))
-        }
-        Ok(httparse::Status::Partial) => Err(ParsingError::Partial),
-        Err(httparse::Error::TooManyHeaders) => Err(ParsingError::TooManyHeaders),
-        Err(err) => {
-            warn!("Parsing error: {}", err);
-            Err(ParsingError::Syntax)
+/// Whether the body is sent in chunks, rather than with its length declared. A length declared
+/// along with a transfer coding is refused, as a proxy in front could go by the other one, and so
+/// are codings not ending with a single `chunked`, which leave the body's end unknown.
+fn is_chunked(req: &Request) -> Result<bool, ReadError> {
+    let values: Vec<_> = req.header_values("Transfer-Encoding").collect();
+    if values.is_empty() {
+        return Ok(false);
+    }
+    if req.header("Content-Length").is_some() {
+        return Err(ReadError::BadSyntax(Some(
+            "Content-Length must not be sent along with Transfer-Encoding.".into(),
+        )));
+    }
+    let codings: Vec<_> = values
+        .iter()
+        .flat_map(|value| value.split(|&b| b == b','))
+        .map(|coding| coding.trim_ascii())
+        .filter(|coding| !coding.is_empty())
+        .collect();
+    let chunked = |coding: &[u8]| coding.eq_ignore_ascii_case(b"chunked");
+    match codings.split_last() {
+        Some((last, others)) if chunked(last) && !others.iter().any(|coding| chunked(coding)) => {
+            if others.is_empty() {
+                Ok(true)
+            } else {
+                Err(ReadError::UnsupportedCoding)
+            }
         }
+        _ => Err(ReadError::BadSyntax(Some(
+            "Transfer-Encoding must end with chunked, sent once.".into(),
+        ))),
     }
 }
 
-fn get_content_length(req: &Request) -> Result<u32, ReadResult> {
-    let content_length = req
-        .headers
-        .get("Content-Length")
+fn get_content_length(req: &Request) -> Result<u64, ReadError> {
+    req.header("Content-Length")
         .map(|v| match String::from_utf8(v.to_owned()) {
             Ok(s) => match s.parse() {
-                Ok(d) => Ok(d),
-                Err(_) => Err(ReadError::BadSyntax(Some(
+                // Parsing takes a leading `+` as well, which the grammar has no room for.
+                Ok(d) if s.bytes().all(|b| b.is_ascii_digit()) => Ok(d),
+                _ => Err(ReadError::BadSyntax(Some(
                     "Content-Length value must be an integer.".into(),
                 ))),
             },
@@ -119,9 +494,5 @@ fn get_content_length(req: &Request) -> Result<u32, ReadResult> {
                 "Content-Length contains non-UTF8 characters.".into(),
             ))),
         })
-        .unwrap_or(Ok(0));
-    match content_length {
-        Ok(len) => Ok(len),
-        Err(err) => Err(ReadResult::Err(err)),
-    }
+        .unwrap_or(Ok(0))
 }
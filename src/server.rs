@@ -0,0 +1,851 @@
+//! Listening on the addresses of hosts and answering requests of the connections they accept.
+
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use scoped_threadpool::Pool;
+#[cfg(unix)]
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+#[cfg(unix)]
+use signal_hook::iterator::Signals;
+use tracing::{error, field, info, info_span, warn};
+
+use crate::http::{
+    copy_chunked, too_many_requests, Request, Response, Status, Tail, SECURITY_HEADERS,
+};
+use crate::rate_limit::RateLimiter;
+use crate::reader::{read_request, Body, ReadError, RequestBuffer};
+use crate::utils::{
+    bind_listener, client_ip, init_mime_types, is_fd_exhaustion, is_peer_allowed, is_trusted_proxy,
+    parse_host, raise_open_files_limit,
+};
+use crate::{access_log, file_cache, get_hosts, metrics, static_server, watch, HostData};
+use crate::{ConcurrencyModel, Config, DomainHandler, ServerState, ServerTokens};
+
+/// How long an idle listener sleeps before checking for connections and shutdown again.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long a listener waits before accepting again, after running out of file descriptors.
+const FD_EXHAUSTION_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Accepted connections waiting for a shared worker, per worker. Once they are queued, listeners
+/// stop accepting and further connections wait in the system's backlog instead of in memory.
+const QUEUED_CONNECTIONS_PER_WORKER: usize = 16;
+
+/// Shuts a running server down: its listeners stop accepting and let connections already in
+/// progress finish, after which `serve` returns.
+///
+/// Listeners accept connections in non-blocking mode and check for the message it sends between
+/// polls, so they notice it within one poll interval.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    senders: Vec<crossbeam_channel::Sender<()>>,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        for sender in &self.senders {
+            // A full channel means the message is already waiting.
+            let _ = sender.try_send(());
+        }
+    }
+}
+
+/// Server of the hosts found as configured, embeddable in other programs.
+///
+/// ```no_run
+/// use clap::Parser;
+/// use webserver::{Config, Server};
+///
+/// let config = Config::parse_from(["webserver", "content", "--port", "8080"]);
+/// let server = Server::new(config).expect("Invalid error page");
+/// let (stop, stopped) = std::sync::mpsc::channel();
+/// std::thread::spawn(move || server.run_until(stopped));
+/// // ...
+/// stop.send(()).unwrap();
+/// ```
+pub struct Server {
+    config: Config,
+}
+
+impl Server {
+    /// Fails if an `--error-page` doesn't exist.
+    pub fn new(mut config: Config) -> Result<Server, String> {
+        config.resolve_error_pages()?;
+        Ok(Server { config })
+    }
+
+    /// Serves until SIGINT (Ctrl-C) or SIGTERM. SIGHUP is ignored, so the server survives its
+    /// terminal being closed.
+    pub fn run(self) -> Result<(), String> {
+        serve(self.config, |_, shutdown| spawn_signal_handler(shutdown))
+    }
+
+    /// Serves until a message comes through `shutdown`, or its sender is dropped.
+    pub fn run_until(self, shutdown: mpsc::Receiver<()>) -> Result<(), String> {
+        serve(self.config, |_, handle| {
+            thread::Builder::new()
+                .name("webserver: shutdown".into())
+                .spawn(move || {
+                    // Either way, nothing will tell the server to stop anymore.
+                    let _ = shutdown.recv();
+                    handle.shutdown();
+                })
+                .expect("Failed to spawn shutdown thread.");
+        })
+    }
+}
+
+/// Serves the configured hosts until shut down. Once all addresses are bound, `ready` is given
+/// the ones listened on, with ports picked by the system in place of 0, and the handle to shut
+/// the server down with. Error pages are served as given, so `Config::resolve_error_pages` should
+/// have been called on the configuration.
+pub fn serve(
+    config: Config,
+    ready: impl FnOnce(&[SocketAddr], ShutdownHandle),
+) -> Result<(), String> {
+    init_process(&config)?;
+    let hosts = HashMap::new();
+    let rate_limiter = (config.rate_limit > 0).then(|| RateLimiter::new(config.rate_limit));
+    let mut server_state = ServerState {
+        config,
+        hosts,
+        connections: OpenConnections::default(),
+        rate_limiter,
+    };
+    let hosts = get_hosts(&server_state.config)?;
+    for host in hosts {
+        server_state
+            .hosts
+            .insert(host.get_hostname().to_ascii_lowercase(), host);
+    }
+    if let Some(name) = &server_state.config.default_host {
+        if !server_state.hosts.contains_key(&name.to_ascii_lowercase()) {
+            warn!("Default host {name} is not served; ignoring");
+        }
+    }
+    let server_state = &server_state;
+
+    let watcher = server_state
+        .config
+        .watch
+        .then(|| watch_content(server_state))
+        .flatten();
+
+    let (listeners, mut senders) = bind_listeners(server_state);
+    let (stop, stopping) = crossbeam_channel::bounded(1);
+    senders.push(stop);
+    let bound: Vec<_> = listeners.iter().map(|(address, ..)| *address).collect();
+    ready(&bound, ShutdownHandle { senders });
+
+    // Outside of per-host mode, listeners pass connections to workers shared by all of them.
+    let workers = match server_state.config.concurrency_model {
+        ConcurrencyModel::PerHost => 0,
+        ConcurrencyModel::Shared => server_state.config.worker_threads.into(),
+        ConcurrencyModel::Single => 1,
+    };
+    let (connections, queue) =
+        crossbeam_channel::bounded::<Connection>(workers * QUEUED_CONNECTIONS_PER_WORKER);
+    // Connections don't end by themselves once the server stops; the timer closes them.
+    let (finished, done) = crossbeam_channel::bounded::<()>(0);
+    thread::scope(|outer| {
+        thread::Builder::new()
+            .name("webserver: shutdown timer".into())
+            .spawn_scoped(outer, || {
+                enforce_shutdown_timeout(server_state, &stopping, &done)
+            })
+            .expect("Failed to spawn shutdown timer thread.");
+        thread::scope(|scope| {
+            for i in 0..workers {
+                let queue = queue.clone();
+                thread::Builder::new()
+                    .name(format!("webserver: worker {i}"))
+                    .spawn_scoped(scope, move || {
+                        // Ends once all listeners have closed and the queue is drained.
+                        for (hosts, stream, peer) in queue {
+                            handle_connection(server_state, hosts, stream, peer);
+                        }
+                    })
+                    .expect("Failed to spawn worker thread.");
+            }
+            for (address, listener, hosts, recv) in &listeners {
+                let shared = (workers > 0).then(|| connections.clone());
+                thread::Builder::new()
+                    .name(format!("webserver: {address} listener"))
+                    .spawn_scoped(scope, move || {
+                        listen(
+                            server_state,
+                            *address,
+                            listener,
+                            hosts,
+                            recv,
+                            shared.as_ref(),
+                        );
+                    })
+                    .expect("Failed to spawn listener thread.");
+            }
+            drop(connections);
+        });
+        drop(finished);
+    });
+    drop(watcher);
+    Ok(())
+}
+
+/// A listener with the address it is bound to, the hosts it serves and the receiver of the
+/// message to stop.
+type Listener<'a, 's> = (
+    SocketAddr,
+    TcpListener,
+    Vec<&'a DomainHandler<'s>>,
+    crossbeam_channel::Receiver<()>,
+);
+
+/// Binds listeners for the addresses of hosts, returning them with the senders of messages
+/// telling them to stop. Addresses failing to bind are skipped.
+fn bind_listeners<'a, 's>(
+    state: &'a ServerState<'s>,
+) -> (Vec<Listener<'a, 's>>, Vec<crossbeam_channel::Sender<()>>) {
+    // Hosts resolving to the same address share its listener, which tells them apart by the
+    // Host header of requests.
+    let mut addresses: BTreeMap<SocketAddr, Vec<&DomainHandler>> = BTreeMap::new();
+    for host in state.hosts.values() {
+        for address in host.get_addresses() {
+            addresses.entry(*address).or_default().push(host);
+        }
+    }
+    let mut senders = Vec::new();
+    let mut listeners = Vec::new();
+    for (address, mut hosts) in addresses {
+        let listener = match bind_listener(address, state.config.ipv6_only) {
+            Ok(listener) => listener,
+            Err(err) => {
+                warn!("Failed to bind an address ({address}): {err}.");
+                continue;
+            }
+        };
+        let address = listener.local_addr().unwrap_or(address);
+        hosts.sort_by(|a, b| a.get_hostname().cmp(b.get_hostname()));
+        for host in &hosts {
+            println!(
+                "Server is listening on http://{}:{} (http://{address})\n",
+                host.get_hostname(),
+                address.port(),
+            );
+        }
+        let (tx, rx) = crossbeam_channel::bounded(1);
+        senders.push(tx);
+        listeners.push((address, listener, hosts, rx));
+    }
+    (listeners, senders)
+}
+
+/// Once told to stop, gives connections the configured time to finish, until the server is
+/// `done`; then closes the remaining ones.
+fn enforce_shutdown_timeout(
+    state: &ServerState,
+    stop: &crossbeam_channel::Receiver<()>,
+    done: &crossbeam_channel::Receiver<()>,
+) {
+    crossbeam_channel::select! {
+        // Without a handle left, nothing can stop the server.
+        recv(stop) -> message => if message.is_err() { return },
+        recv(done) -> _ => return,
+    }
+    let timeout = Duration::from_secs(state.config.shutdown_timeout);
+    if timeout.is_zero() {
+        return;
+    }
+    if let Err(crossbeam_channel::RecvTimeoutError::Timeout) = done.recv_timeout(timeout) {
+        let closed = state.connections.close_all();
+        warn!(
+            closed,
+            "Shutdown timeout passed, closing remaining connections"
+        );
+    }
+}
+
+/// Streams of connections being handled, for closing them when shutdown takes too long.
+#[derive(Default)]
+pub(crate) struct OpenConnections {
+    streams: Mutex<HashMap<u64, TcpStream>>,
+    next_id: AtomicU64,
+    /// Whether remaining connections were closed; any still coming are closed right away.
+    closed: AtomicBool,
+}
+
+impl OpenConnections {
+    /// Tracks the connection until the returned guard is dropped.
+    fn register(&self, stream: &TcpStream) -> Option<Registration<'_>> {
+        let stream = stream
+            .try_clone()
+            .map_err(|err| warn!("Failed to track connection: {err}"))
+            .ok()?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut streams = self.streams.lock().unwrap();
+        if self.closed.load(Ordering::Relaxed) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        streams.insert(id, stream);
+        Some(Registration {
+            connections: self,
+            id,
+        })
+    }
+
+    /// Closes all tracked connections, returning how many there were. Handlers blocked on them
+    /// fail, and so finish.
+    fn close_all(&self) -> usize {
+        let streams = self.streams.lock().unwrap();
+        self.closed.store(true, Ordering::Relaxed);
+        for stream in streams.values() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        streams.len()
+    }
+}
+
+struct Registration<'a> {
+    connections: &'a OpenConnections,
+    id: u64,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.connections.streams.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Sets up what the configuration asks of the whole process. Only the first server in a process
+/// can enable the file cache, content type settings and the access log.
+fn init_process(config: &Config) -> Result<(), String> {
+    if let Some(target) = config.max_open_files {
+        report_open_files_limit(target);
+    }
+    if config.cache_size_bytes > 0 {
+        file_cache::init(config.cache_size_bytes, config.cache_max_file_bytes);
+    }
+    if !config.mime_overrides.is_empty()
+        || config.default_mime != mime_guess::mime::APPLICATION_OCTET_STREAM
+    {
+        init_mime_types(&config.mime_overrides, config.default_mime.clone());
+    }
+    if let Some(path) = &config.access_log {
+        access_log::init(path)
+            .map_err(|err| format!("Failed to open access log {}: {err}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Raises the open files limit towards `target`, logging the outcome.
+fn report_open_files_limit(target: u64) {
+    match raise_open_files_limit(target) {
+        Ok(limit) if limit < target => warn!("Open files limit is only {limit}"),
+        Ok(limit) => info!("Open files limit is {limit}"),
+        Err(err) => warn!("Failed to raise open files limit: {err}"),
+    }
+}
+
+#[cfg(unix)]
+fn spawn_signal_handler(shutdown: ShutdownHandle) {
+    let mut signals =
+        Signals::new([SIGINT, SIGTERM, SIGHUP]).expect("Failed to set termination handler");
+    thread::Builder::new()
+        .name("webserver: signal handler".into())
+        .spawn(move || {
+            for signal in &mut signals {
+                if signal == SIGHUP {
+                    info!("Ignoring SIGHUP");
+                    continue;
+                }
+                info!("Attempting to terminate threads");
+                shutdown.shutdown();
+            }
+        })
+        .expect("Failed to spawn signal handler thread.");
+}
+
+/// Without Unix signals, only Ctrl-C shuts the server down; the flag it raises is polled.
+#[cfg(not(unix))]
+fn spawn_signal_handler(shutdown: ShutdownHandle) {
+    let interrupted = std::sync::Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGINT, interrupted.clone())
+        .expect("Failed to set termination handler");
+    thread::Builder::new()
+        .name("webserver: signal handler".into())
+        .spawn(move || loop {
+            if interrupted.swap(false, Ordering::Relaxed) {
+                info!("Attempting to terminate threads");
+                shutdown.shutdown();
+            }
+            thread::sleep(ACCEPT_POLL_INTERVAL);
+        })
+        .expect("Failed to spawn signal handler thread.");
+}
+
+/// An accepted connection, with the hosts of the listener it came to.
+type Connection<'a, 's> = (&'a [&'a DomainHandler<'s>], TcpStream, SocketAddr);
+
+/// Watches content directories of static hosts; the watcher stops once dropped.
+fn watch_content(state: &ServerState) -> Option<notify::RecommendedWatcher> {
+    let dirs = state.hosts.values().filter_map(|host| match host {
+        DomainHandler::StaticDir(data) => Some(data.content_dir()),
+        DomainHandler::Executable(_) => None,
+    });
+    watch::start(dirs)
+        .map_err(|err| warn!("Failed to watch content: {err}"))
+        .ok()
+}
+
+/// Accepts connections on the listener for its hosts. Requests naming none of them are served by
+/// the first one by name, unless a default host is configured.
+///
+/// Connections are handled by the listener's own pool, unless `shared` workers are given.
+fn listen<'a, 's>(
+    state: &'a ServerState<'s>,
+    address: SocketAddr,
+    listener: &TcpListener,
+    hosts: &'a [&'a DomainHandler<'s>],
+    recv: &crossbeam_channel::Receiver<()>,
+    shared: Option<&crossbeam_channel::Sender<Connection<'a, 's>>>,
+) {
+    let span = info_span!("", address = address.to_string());
+    let _enter = span.enter();
+
+    if let Err(err) = listener.set_nonblocking(true) {
+        warn!("Failed to make listener non-blocking: {err}.");
+        return;
+    }
+
+    if let Some(workers) = shared {
+        accept_connections(listener, recv, |stream, peer| {
+            // Workers outlive listeners, so the queue stays open.
+            let _ = workers.send((hosts, stream, peer));
+        });
+    } else {
+        let mut pool = Pool::new(state.config.worker_threads.into());
+        pool.scoped(|scope| {
+            accept_connections(listener, recv, |stream, peer| {
+                scope.execute(move || handle_connection(state, hosts, stream, peer));
+            });
+        });
+    }
+    info!("Listener closed");
+}
+
+/// Passes accepted connections on until told to stop through `recv`.
+fn accept_connections(
+    listener: &TcpListener,
+    recv: &crossbeam_channel::Receiver<()>,
+    mut dispatch: impl FnMut(TcpStream, SocketAddr),
+) {
+    loop {
+        if recv.try_recv().is_ok() {
+            info!("Closing listener");
+            break;
+        }
+        let stream = listener.accept();
+        match stream {
+            Ok((stream, peer)) => {
+                if let Err(err) = stream.set_nonblocking(false) {
+                    error!("connection failed: {err}");
+                    continue;
+                }
+                dispatch(stream, peer);
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+            // Pending connections wait in the backlog until descriptors free up.
+            Err(err) if is_fd_exhaustion(&err) => {
+                warn!("Out of file descriptors, pausing accepting connections");
+                thread::sleep(FD_EXHAUSTION_BACKOFF);
+            }
+            Err(err) => error!("connection failed: {err}"),
+        }
+    }
+}
+
+/// Serves requests on the connection by the listener's hosts, the first of which owns it.
+fn handle_connection(
+    state: &ServerState,
+    hosts: &[&DomainHandler],
+    mut stream: TcpStream,
+    peer: SocketAddr,
+) {
+    let host = hosts[0];
+    let span = info_span!("connection", peer = peer.to_string());
+    let _enter = span.enter();
+
+    info!("Connected");
+    let _registration = state.connections.register(&stream);
+
+    let config = host.get_config();
+    // Clients behind a trusted proxy are checked by each request instead.
+    let proxied = is_trusted_proxy(peer.ip(), &config.trusted_proxies);
+    if !proxied && !is_peer_allowed(peer.ip(), &config.allow, &config.deny) {
+        info!("Peer denied");
+        if !config.drop_denied {
+            write_response(&mut stream, config, Response::new(Status::Forbidden), true);
+        }
+        return;
+    }
+    metrics::connection_opened();
+    let connected = Instant::now();
+    let max_duration = Duration::from_secs(config.connection_max_duration);
+
+    let mut buffer = RequestBuffer::new();
+    let mut requests = 0;
+    loop {
+        let close_connection;
+        let mut timing = None;
+        let mut traffic = None;
+        let mut simple = false;
+        let response = match read_request(&mut stream, host.get_config(), &mut buffer) {
+            Ok((request, mut body)) => {
+                requests += 1;
+                metrics::request_received();
+                let client = client_ip(peer.ip(), &request, &config.trusted_proxies);
+                let entry = access_log::Entry::new(client, &request);
+                let target = format!("{} {}", request.method, request.path);
+                simple = request.simple;
+                let version = request.version;
+                // Timing starts once the request is parsed, so waiting for it doesn't count.
+                let started = Instant::now();
+                let span = info_span!(
+                    "request",
+                    target,
+                    client = client.to_string(),
+                    duration_ms = field::Empty,
+                    bytes_sent = field::Empty
+                );
+                let (response, close, served_by) = {
+                    let _enter = span.enter();
+                    serve_request(state, hosts, client, request, &mut body)
+                };
+                timing = Some((span, target, started, started.elapsed()));
+                traffic = Some((served_by.get_traffic(), body.received()));
+                entry.record(&response);
+                // HTTP/0.9 responses end with the connection, and so does streamed content
+                // for HTTP/1.0 clients, which don't know chunks.
+                let expired = !max_duration.is_zero() && connected.elapsed() >= max_duration;
+                close_connection =
+                    close || simple || expired || (response.is_streamed() && version == 0);
+                Some(response)
+            }
+            // Whatever follows a request that couldn't be read cannot be framed.
+            Err(err) => {
+                close_connection = true;
+                read_error_response(err)
+            }
+        };
+        if let Some(response) = response {
+            let _enter = timing.as_ref().map(|(span, ..)| span.enter());
+            let sent = if simple {
+                write_simple_response(&mut stream, host.get_config(), response)
+            } else {
+                write_response(&mut stream, host.get_config(), response, close_connection)
+            };
+            if let Some((traffic, received)) = traffic {
+                traffic.record(received, sent);
+            }
+
+            if let Some((span, target, started, handling)) = &timing {
+                let total = started.elapsed();
+                span.record("duration_ms", total.as_secs_f64() * 1000.0);
+                span.record("bytes_sent", sent);
+                info!("Request completed");
+                report_slow_request(host.get_config(), target, *handling, total);
+            }
+        }
+        if close_connection {
+            metrics::connection_closed(requests);
+            info!(requests, "Disconnected");
+            return;
+        }
+    }
+}
+
+/// Answers a request that couldn't be read, unless the connection is gone already.
+fn read_error_response(err: ReadError) -> Option<Response> {
+    let response = match err {
+        ReadError::ConnectionClosed => return None,
+        ReadError::Timeout => Response::new(Status::RequestTimeout),
+        ReadError::BadSyntax(None) | ReadError::TooManyHeaders => Response::new(Status::BadRequest),
+        ReadError::BadSyntax(Some(msg)) => Response::with_content(Status::BadRequest, msg),
+        ReadError::UnsupportedCoding => Response::new(Status::NotImplemented),
+        ReadError::HeadTooLarge => Response::new(Status::RequestHeaderFieldsTooLarge),
+        ReadError::OutOfMemory => {
+            warn!("Buffer memory budget exceeded");
+            Response::new(Status::ServiceUnavailable)
+        }
+    };
+    Some(response)
+}
+
+/// Handles the request, unless the client is refused or over its rate limit, and consumes what
+/// the handler left of its body. A panic while handling becomes a 500 response. Any response gets
+/// the CORS headers of the host which served the request. Returns the response, whether to close
+/// the connection and that host.
+fn serve_request<'a, 's>(
+    state: &ServerState<'s>,
+    hosts: &[&'a DomainHandler<'s>],
+    client: IpAddr,
+    request: Request,
+    body: &mut Body<'_>,
+) -> (Response, bool, &'a DomainHandler<'s>) {
+    let host = hosts[0];
+    let config = host.get_config();
+    let cors = static_server::CorsRequest::new(&request);
+    let handled = if !is_peer_allowed(client, &config.allow, &config.deny) {
+        info!("Client denied");
+        Ok((Response::new(Status::Forbidden), true, host))
+    } else if let Some(Err(wait)) = state
+        .rate_limiter
+        .as_ref()
+        .map(|limiter| limiter.acquire(client))
+    {
+        Ok((too_many_requests(wait), false, host))
+    } else {
+        catch_handler_panic(|| handle_request(state, hosts, request, body))
+    };
+    let (mut response, close, served_by) = match handled {
+        // The next request starts only after whatever the handler left of this body.
+        Ok((response, close, served_by)) => match body.discard() {
+            Ok(()) => (response, close, served_by),
+            Err(ReadError::BadSyntax(Some(msg))) => (
+                Response::with_content(Status::BadRequest, msg),
+                true,
+                served_by,
+            ),
+            Err(_) => (response, true, served_by),
+        },
+        // The body may be left mid-read, so the connection can't be reused.
+        Err(response) => (response, true, host),
+    };
+    if let DomainHandler::StaticDir(data) = served_by {
+        cors.add_headers(&mut response, data);
+    }
+    (response, close, served_by)
+}
+
+/// Runs a request handler, logging a panic in it and answering it with 500 Internal Server Error.
+pub fn catch_handler_panic<T>(handler: impl FnOnce() -> T) -> Result<T, Response> {
+    panic::catch_unwind(AssertUnwindSafe(handler)).map_err(|payload| {
+        error!(
+            panic = panic_message(&*payload),
+            "Request handling panicked"
+        );
+        Response::new(Status::InternalServerError)
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown"
+    }
+}
+
+/// Finalizes and writes the response, returning its size in bytes.
+fn write_response(
+    stream: &mut TcpStream,
+    config: &Config,
+    mut response: Response,
+    close: bool,
+) -> usize {
+    let now = SystemTime::now();
+
+    response.set_header("Date", httpdate::fmt_http_date(now));
+
+    match (&config.server_name, config.server_tokens) {
+        _ if config.no_server_header => response.remove_header("Server"),
+        (Some(name), _) => response.set_header("Server", name.as_str()),
+        (None, ServerTokens::Name) => {}
+        (None, ServerTokens::Version) => {
+            let server = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+            response.set_header("Server", server);
+        }
+        (None, ServerTokens::Off) => response.remove_header("Server"),
+    }
+
+    add_configured_headers(config, &mut response);
+
+    write_connection_header(close, &mut response);
+    // Otherwise, closing the connection delimits streamed content.
+    let chunked = response.is_streamed() && !close;
+    if chunked {
+        response.set_header("Transfer-Encoding", "chunked");
+    }
+
+    info!(response = response.status_line(), "Responded");
+    let status_code = response.status().code();
+    let (response, tail) = response.render();
+    let chunk_size = config.file_chunk_size as usize;
+    send_rendered(stream, status_code, &response, tail, chunked, chunk_size)
+}
+
+/// Writes a rendered response followed by its tail, a file of which is sent `chunk_size` bytes
+/// at a time. Returns their size in bytes.
+fn send_rendered(
+    stream: &mut TcpStream,
+    status_code: u16,
+    response: &[u8],
+    tail: Option<Tail>,
+    chunked: bool,
+    chunk_size: usize,
+) -> usize {
+    stream
+        .write_all(response)
+        .unwrap_or_else(|err| error!("Error writing response: {err}"));
+    let tail_length = match tail {
+        None => Ok(0),
+        Some(Tail::File(file)) => file.send_to(stream, chunk_size).map(|()| file.len()),
+        Some(Tail::Stream(mut content)) if chunked => copy_chunked(&mut content, stream),
+        Some(Tail::Stream(mut content)) => io::copy(&mut content, stream),
+    };
+    let tail_length = tail_length.unwrap_or_else(|err| {
+        error!("Error sending content: {err}");
+        // The promised content was not sent in full, so nothing after it could be framed.
+        let _ = stream.shutdown(Shutdown::Both);
+        0
+    });
+    let length = response
+        .len()
+        .saturating_add(usize::try_from(tail_length).unwrap_or(usize::MAX));
+    metrics::response_sent(status_code, length);
+
+    stream
+        .flush()
+        .unwrap_or_else(|err| error!("Error flushing response: {err}"));
+    length
+}
+
+/// Adds the headers configured for every response. Those given one by one take precedence over
+/// the ones from the security preset.
+fn add_configured_headers(config: &Config, response: &mut Response) {
+    if config.security_headers {
+        for (name, value) in SECURITY_HEADERS {
+            response.set_header(name, value);
+        }
+    }
+    if config.hsts {
+        response.set_header("Strict-Transport-Security", "max-age=31536000");
+    }
+    if config.no_sniff {
+        response.set_header("X-Content-Type-Options", "nosniff");
+    }
+    if let Some(value) = &config.frame_options {
+        response.set_header("X-Frame-Options", value.as_str());
+    }
+    for (name, value) in &config.extra_headers {
+        response.set_header(name, value.as_str());
+    }
+}
+
+/// Writes the content of a response to an HTTP/0.9 request, returning its size in bytes.
+fn write_simple_response(stream: &mut TcpStream, config: &Config, response: Response) -> usize {
+    info!(response = response.status_line(), "Responded");
+    let status_code = response.status().code();
+    // The connection closes after the content, which delimits it.
+    let (content, tail) = response.render_simple();
+    let chunk_size = config.file_chunk_size as usize;
+    send_rendered(stream, status_code, &content, tail, false, chunk_size)
+}
+
+fn report_slow_request(config: &Config, target: &str, handling: Duration, total: Duration) {
+    let threshold = Duration::from_millis(config.slow_request_threshold);
+    if !threshold.is_zero() && total > threshold {
+        warn!(
+            request = target,
+            total_ms = total.as_secs_f64() * 1000.0,
+            handling_ms = handling.as_secs_f64() * 1000.0,
+            writing_ms = total.saturating_sub(handling).as_secs_f64() * 1000.0,
+            "Slow request"
+        );
+    }
+}
+
+fn write_connection_header(close: bool, response: &mut Response) {
+    let connection_header = if close { "close" } else { "keep-alive" };
+    response.set_header("Connection", connection_header);
+}
+
+/// Serves the request by the listener's host named in its `Host` header, or by the default host
+/// (if the listener has it, else the host owning the listener) when the name is unknown or,
+/// before HTTP/1.1, missing. Hosts of other listeners are never picked, as the request did not
+/// reach their addresses. Returns the response, whether to close the connection and the host
+/// which served the request.
+fn handle_request<'a, 's>(
+    state: &ServerState<'s>,
+    hosts: &[&'a DomainHandler<'s>],
+    request: Request,
+    body: &mut Body<'_>,
+) -> (Response, bool, &'a DomainHandler<'s>) {
+    info!("Request received");
+
+    let has_token = |wanted: &[u8]| {
+        request.header_values("Connection").any(|v| {
+            v.split(|&b| b == b',')
+                .any(|token| token.trim_ascii().eq_ignore_ascii_case(wanted))
+        })
+    };
+    // HTTP/1.0 connections close after a request unless the client asks to keep them.
+    let mut close = state.config.keep_alive_timeout().is_zero()
+        || has_token(b"close")
+        || (request.version == 0 && !has_token(b"keep-alive"));
+
+    let own_host = hosts[0];
+    let find_host = |name: &str| {
+        hosts
+            .iter()
+            .copied()
+            .find(|host| host.get_hostname().eq_ignore_ascii_case(name))
+    };
+    let fallback = state
+        .config
+        .default_host
+        .as_deref()
+        .and_then(find_host)
+        .unwrap_or(own_host);
+    if request.header_values("Host").nth(1).is_some() {
+        let response = Response::with_content(Status::BadRequest, "Multiple Host headers.");
+        return (response, close, own_host);
+    }
+    let handler = match request.header("Host").map(parse_host) {
+        // HTTP/1.1 requires the header; earlier versions are served by the fallback.
+        None if request.version == 1 => {
+            let response = Response::with_content(Status::BadRequest, "Missing Host header.");
+            return (response, close, own_host);
+        }
+        None => fallback,
+        Some(Some(hostname)) => find_host(&hostname).unwrap_or(fallback),
+        Some(None) => {
+            let response = Response::with_content(Status::BadRequest, "Malformed Host header.");
+            return (response, close, own_host);
+        }
+    };
+
+    let response = match handler {
+        DomainHandler::StaticDir(data) => static_server::handle_request(request, body, data),
+        DomainHandler::Executable(_) => {
+            close = true;
+            Response::with_content(
+                Status::NotImplemented,
+                "Dynamic http servers not yet supported",
+            )
+        }
+    };
+
+    (response, close, handler)
+}
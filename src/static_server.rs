@@ -1,20 +1,33 @@
 use std::{
     collections::HashMap,
+    fs::Metadata,
     io,
     net::SocketAddr,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
-use tracing::info;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use tracing::{info, warn};
 
-use crate::{http::*, utils::path_if_existing, Config, HostData};
+use crate::file_cache;
+use crate::utils::{
+    constant_time_eq, decode_safe_path, encoding_quality, is_fd_exhaustion, match_file_type,
+    parse_accept_encoding, path_if_existing,
+};
+use crate::metrics::{self, HostTraffic};
+use crate::reader::Body;
+use crate::{http::*, Config, HostData};
 
 pub struct Data<'a> {
+    /// The directory to serve, or the file to answer every request with.
     content_dir: PathBuf,
+    single_file: bool,
     handlers: HashMap<String, MethodHandler>,
     config: &'a Config,
-    address: SocketAddr,
+    addresses: Vec<SocketAddr>,
     hostname: String,
+    traffic: Arc<HostTraffic>,
 }
 
 impl HostData<'_> for Data<'_> {
@@ -22,118 +35,534 @@ impl HostData<'_> for Data<'_> {
         self.config
     }
 
-    fn get_address(&self) -> &SocketAddr {
-        &self.address
+    fn get_addresses(&self) -> &[SocketAddr] {
+        &self.addresses
     }
 
     fn get_hostname(&self) -> &String {
         &self.hostname
     }
+
+    fn get_traffic(&self) -> &HostTraffic {
+        &self.traffic
+    }
+}
+
+impl Data<'_> {
+    /// The directory served, or the file served in single-file mode.
+    pub fn content_dir(&self) -> &Path {
+        &self.content_dir
+    }
 }
 
 impl<'a> Data<'a> {
     pub fn new(
         content_dir: PathBuf,
         config: &'a Config,
-        address: SocketAddr,
+        addresses: Vec<SocketAddr>,
         hostname: String,
-    ) -> Data {
+    ) -> Data<'a> {
         Data {
+            single_file: content_dir.is_file(),
             content_dir,
-            handlers: get_handlers(),
+            handlers: get_handlers(config),
             config,
-            address,
+            addresses,
+            traffic: metrics::register_host(&hostname),
             hostname,
         }
     }
 }
 
-type MethodHandler = Box<dyn Fn(&Data, &Request) -> Response + Sync>;
+/// Methods defined by HTTP; others are not recognized at all, rather than not allowed.
+const KNOWN_METHODS: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+/// Handles a request; its body may be read from the given stream, and whatever is left of it is
+/// discarded afterwards.
+type MethodHandler = Box<dyn Fn(&Data, &Request, &mut Body<'_>) -> Response + Sync>;
 
-pub fn handle_request(request: Request, data: &Data) -> Response {
-    let Some(handler) = data.handlers.get(&request.method) else {
-            let mut resp = Response::new(Status::MethodNotAllowed);
-            let allowed_methods = data.handlers.keys().map(|s| &**s).collect::<Vec<_>>().join(", ");
-            resp.set_header("Allow", allowed_methods);
-            return resp;
+pub fn handle_request(mut request: Request, body: &mut Body<'_>, data: &Data) -> Response {
+    // The asterisk form of OPTIONS targets the whole server, not a path under the base.
+    if let Some(base) = data
+        .config
+        .base_path
+        .as_ref()
+        .filter(|_| request.path != "*")
+    {
+        // Like any other directory, the base gets its trailing slash before its index is served.
+        if request.path == *base && matches!(request.method.as_str(), "GET" | "HEAD") {
+            return redirect_dir("", None, data);
+        }
+        match strip_base_path(&request.path, base) {
+            Some(path) => request.path = path,
+            None => return load_error(Status::NotFound, data),
+        }
+    }
+    if data.config.health_path.as_deref() == Some(request.path_without_query())
+        && matches!(request.method.as_str(), "GET" | "HEAD")
+    {
+        return health_check(&request);
+    }
+    if data.config.metrics_path.as_deref() == Some(request.path_without_query())
+        && matches!(request.method.as_str(), "GET" | "HEAD")
+    {
+        return serve_metrics(&request);
+    }
+    if !is_authorized(&request, data.config) {
+        let mut resp = load_error(Status::Unauthorized, data);
+        let challenge = format!("Basic realm=\"{}\", charset=\"UTF-8\"", data.hostname);
+        resp.set_header("WWW-Authenticate", challenge);
+        return resp;
+    }
+    if in_maintenance(&request, data.config) {
+        return load_error(Status::ServiceUnavailable, data);
+    }
+
+    if let Some(handler) = data.handlers.get(&request.method) {
+        handler(data, &request, body)
+    } else if !KNOWN_METHODS.contains(&request.method.as_str()) {
+        Response::new(Status::NotImplemented)
+    } else {
+        if request.method == "TRACE" {
+            warn!("Refused TRACE request");
+        }
+        let mut resp = Response::new(Status::MethodNotAllowed);
+        resp.set_header("Allow", allowed_methods(data));
+        resp
+    }
+}
+
+/// Request headers needed to answer CORS, captured before the request is handled so that any
+/// response to it gets CORS headers, however it was produced.
+pub struct CorsRequest {
+    origin: Option<Vec<u8>>,
+    requested_headers: Option<Vec<u8>>,
+}
+
+impl CorsRequest {
+    pub fn new(request: &Request) -> CorsRequest {
+        CorsRequest {
+            origin: request.header("Origin").map(<[u8]>::to_vec),
+            requested_headers: request
+                .header("Access-Control-Request-Headers")
+                .filter(|_| request.method == "OPTIONS")
+                .map(<[u8]>::to_vec),
+        }
+    }
+
+    /// Adds CORS headers when the request's origin is allowed; for preflight requests, the
+    /// requested headers are allowed as well.
+    pub fn add_headers(&self, response: &mut Response, data: &Data) {
+        let origins = &data.config.cors_origins;
+        if origins.is_empty() {
+            return;
+        }
+        let any_origin = origins.iter().any(|allowed| allowed == "*");
+        if !any_origin {
+            response.append_header("Vary", "Origin");
+        }
+        let Some(origin) = &self.origin else {
+            return;
         };
+        if any_origin {
+            response.set_header("Access-Control-Allow-Origin", "*");
+        } else if origins.iter().any(|allowed| allowed.as_bytes() == origin) {
+            response.set_header("Access-Control-Allow-Origin", origin.as_slice());
+        } else {
+            return;
+        }
+        response.set_header("Access-Control-Allow-Methods", allowed_methods(data));
+        if let Some(headers) = &self.requested_headers {
+            response.set_header("Access-Control-Allow-Headers", headers.as_slice());
+        }
+    }
+}
+
+fn health_check(request: &Request) -> Response {
+    let mut resp = Response::with_content(Status::Ok, "OK");
+    resp.set_header("Content-Type", "text/plain; charset=utf-8");
+    if request.method == "HEAD" {
+        resp.to_head()
+    } else {
+        resp
+    }
+}
+
+fn serve_metrics(request: &Request) -> Response {
+    let mut resp = Response::new(Status::Ok);
+    resp.add_content(metrics::render());
+    resp.set_header("Content-Type", "text/plain; version=0.0.4");
+    if request.method == "HEAD" {
+        resp.to_head()
+    } else {
+        resp
+    }
+}
+
+/// Whether the request carries the configured Basic credentials, if any are configured. CORS
+/// preflight requests never carry credentials, so they are let through.
+fn is_authorized(request: &Request, config: &Config) -> bool {
+    let (Some(user), Some(pass)) = (&config.auth_user, &config.auth_pass) else {
+        return true;
+    };
+    if request.method == "OPTIONS" && request.header("Access-Control-Request-Method").is_some() {
+        return true;
+    }
+    let Some(credentials) = request
+        .header("Authorization")
+        .and_then(|v| v.split_at_checked(6))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case(b"Basic "))
+        .and_then(|(_, v)| BASE64.decode(v.trim_ascii()).ok())
+    else {
+        return false;
+    };
+    let expected = format!("{user}:{pass}");
+    constant_time_eq(&credentials, expected.as_bytes())
+}
+
+/// Whether the maintenance sentinel file exists and the request falls under a maintained path;
+/// with no paths configured, the whole server is in maintenance.
+fn in_maintenance(request: &Request, config: &Config) -> bool {
+    let Some(sentinel) = &config.maintenance_file else {
+        return false;
+    };
+    if !sentinel.exists() {
+        return false;
+    }
+    if config.maintenance_paths.is_empty() {
+        return true;
+    }
+    // Paths that don't decode are refused by the handlers anyway.
+    let Some(path) = decode_safe_path(request.path_without_query()) else {
+        return false;
+    };
+    config
+        .maintenance_paths
+        .iter()
+        .any(|prefix| has_path_prefix(&path, prefix))
+}
+
+/// Strips the base path the server is mounted under from a request path, so the rest can be
+/// resolved; paths outside of it give `None`.
+fn strip_base_path(path: &str, base: &str) -> Option<String> {
+    let rest = path.strip_prefix(base)?;
+    if rest.is_empty() || rest.starts_with('?') {
+        Some(format!("/{rest}"))
+    } else {
+        rest.starts_with('/').then(|| rest.into())
+    }
+}
 
-    handler(data, &request)
+/// Whether `path` lies under `prefix`, comparing segments the way the path resolves on disk,
+/// where empty and `.` segments lead nowhere.
+fn has_path_prefix(path: &str, prefix: &str) -> bool {
+    fn segments(path: &str) -> impl Iterator<Item = &str> {
+        path.split('/').filter(|s| !s.is_empty() && *s != ".")
+    }
+    let mut path = segments(path);
+    segments(prefix).all(|segment| path.next() == Some(segment))
+}
+
+/// Methods with a handler, sorted so the list doesn't change between responses.
+fn allowed_methods(data: &Data) -> String {
+    let mut methods = data.handlers.keys().map(|s| &**s).collect::<Vec<_>>();
+    methods.sort_unstable();
+    methods.join(", ")
 }
 
-fn get_relative_resource_path(content_dir: &Path, request: &Request) -> PathBuf {
-    let mut rel_res_path = content_dir.to_path_buf();
-    let mut path = request.path.to_string();
-    path.remove(0);
-    rel_res_path.push(&path);
-    rel_res_path
+fn get_relative_resource_path(content_dir: &Path, path: &str) -> PathBuf {
+    // The reader rejects targets not starting with `/`, but an absolute path would replace
+    // `content_dir` when pushed.
+    content_dir.join(path.trim_start_matches('/'))
 }
 
-fn get_handlers() -> HashMap<String, MethodHandler> {
+fn get_handlers(config: &Config) -> HashMap<String, MethodHandler> {
     let mut handlers: HashMap<String, MethodHandler> = HashMap::new();
     handlers.insert("GET".into(), Box::new(handle_get_request));
     handlers.insert("HEAD".into(), Box::new(handle_head_request));
+    handlers.insert("OPTIONS".into(), Box::new(handle_options_request));
+    if config.enable_trace {
+        handlers.insert("TRACE".into(), Box::new(handle_trace_request));
+    }
     handlers
 }
 
-fn handle_get_request(data: &Data, request: &Request) -> Response {
-    let rel_res_path = get_relative_resource_path(&data.content_dir, request);
+fn handle_get_request(data: &Data, request: &Request, _body: &mut Body<'_>) -> Response {
+    // The path checked is the very one resolved, as the query is no part of it.
+    let Some(path) = decode_safe_path(request.path_without_query()) else {
+        return load_error(Status::BadRequest, data);
+    };
+    if data.single_file {
+        return serve_file(&data.content_dir, data, request);
+    }
+    let rel_res_path = get_relative_resource_path(&data.content_dir, &path);
     let res_path = match std::fs::canonicalize(rel_res_path) {
         Ok(path) => path,
         Err(err) => match err.kind() {
-            io::ErrorKind::NotFound => return load_error(Status::NotFound, data),
+            // A path through a file (e.g. `/file.txt/sub`) or with an overlong name is just as
+            // missing as a nonexistent one.
+            io::ErrorKind::NotFound
+            | io::ErrorKind::NotADirectory
+            | io::ErrorKind::InvalidFilename => return not_found(data, request),
             io::ErrorKind::PermissionDenied => {
                 return load_error(Status::Forbidden, data);
             }
+            // Symlink loops have no stable `ErrorKind` yet.
+            #[cfg(unix)]
+            _ if err.raw_os_error() == Some(libc::ELOOP) => return not_found(data, request),
+            _ if is_fd_exhaustion(&err) => return out_of_descriptors(),
             _ => return server_error(err.to_string()),
         },
     };
 
     match res_path.strip_prefix(&data.content_dir) {
-        Ok(rel_res_path) => {
-            if res_path.is_dir() {
-                return redirect_dir(rel_res_path, data);
+        Ok(_) if res_path.is_dir() => {
+            // Relative links in the index resolve against the directory only with the slash.
+            if path.ends_with('/') {
+                serve_index(&res_path, data, request)
+            } else {
+                redirect_dir(request.path_without_query(), request.query(), data)
             }
-            let resp = Response::new(Status::Ok);
-            resp.load_file(&res_path)
         }
+        Ok(_) => serve_file(&res_path, data, request),
         Err(_) => load_error(Status::Forbidden, data),
     }
 }
 
-fn handle_head_request(data: &Data, request: &Request) -> Response {
-    let get_response = handle_get_request(data, request);
+/// Answers a request for a missing resource; with the SPA fallback, paths without an extension
+/// are taken for client-side routes and get the index page instead.
+fn not_found(data: &Data, request: &Request) -> Response {
+    let path = Path::new(request.path_without_query());
+    if data.config.spa_fallback && path.extension().is_none() {
+        let index = data.content_dir.join("index.html");
+        if index.is_file() {
+            return serve_file(&index, data, request);
+        }
+    }
+    load_error(Status::NotFound, data)
+}
+
+/// Precompressed variants looked for beside served files, in order of preference.
+const PRECOMPRESSED: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+fn serve_file(path: &Path, data: &Data, request: &Request) -> Response {
+    // A list-valued header may be split over several lines.
+    let accepted = parse_accept_encoding(
+        &request
+            .header_values("Accept-Encoding")
+            .collect::<Vec<_>>()
+            .join(&b','),
+    );
+    let mut variants = PRECOMPRESSED
+        .iter()
+        .map(|(coding, extension)| (coding, sidecar_path(path, extension)))
+        .filter(|(_, sidecar)| sidecar.is_file())
+        .peekable();
+    let has_variants = variants.peek().is_some();
+    // Among the codings the client prefers most, ours come first; the content as it is goes
+    // only to clients preferring it outright.
+    let quality = |coding| encoding_quality(&accepted, coding).unwrap_or(0.0);
+    let identity = encoding_quality(&accepted, "identity");
+    let variant = variants
+        .filter(|(coding, _)| quality(coding) > 0.0)
+        .reduce(|best, next| {
+            if quality(next.0) > quality(best.0) {
+                next
+            } else {
+                best
+            }
+        })
+        .filter(|(coding, _)| identity.is_none_or(|q| quality(coding) >= q));
+    if variant.is_none() && identity == Some(0.0) {
+        return load_error(Status::NotAcceptable, data);
+    }
+
+    let file = variant
+        .as_ref()
+        .map_or(path, |(_, sidecar)| sidecar.as_path());
+    let threshold = data.config.sendfile_threshold;
+    let mut resp = match file.metadata() {
+        Ok(metadata) if file_cache::is_eligible(metadata.len()) => {
+            load_cached(file, &metadata, data)
+        }
+        Ok(metadata) if threshold != 0 && metadata.len() >= threshold => {
+            Response::new(Status::Ok).send_file(file)
+        }
+        _ => Response::new(Status::Ok).load_file(file, data.config.file_chunk_size as usize),
+    };
+    if !matches!(resp.status(), Status::Ok) {
+        return resp;
+    }
+    if let Some((coding, _)) = variant {
+        resp.set_header("Content-Encoding", *coding);
+        resp.set_header("Content-Type", match_file_type(path));
+    }
+    if has_variants {
+        resp.set_header("Vary", "Accept-Encoding");
+    }
+    if data.config.repr_digest {
+        resp.set_repr_digest(file, data.config.file_chunk_size as usize);
+    }
+    // If-Match applies to every method, not only to writes.
+    if let Some(condition) = request.header("If-Match") {
+        if !resp.matches_if_match(condition) {
+            return load_error(Status::PreconditionFailed, data);
+        }
+    }
+    // Only files are served in ranges, so only they advertise it.
+    resp.set_header("Accept-Ranges", "bytes");
+    // Range is ignored for any method but GET, so HEAD describes the whole file. Once the file
+    // changes from what If-Range names, it is sent whole instead of a range of the new one.
+    let if_range = request.header("If-Range");
+    match request.header("Range") {
+        Some(_) if if_range.is_some_and(|validator| !resp.matches_if_range(validator)) => resp,
+        Some(range) if request.method == "GET" => resp.select_range(range),
+        _ => resp,
+    }
+}
+
+/// Loads the file from the cache if it holds the file as last modified, or from disk otherwise,
+/// caching it then. Concurrent requests for a file missing from the cache share one read of it.
+fn load_cached(path: &Path, metadata: &Metadata, data: &Data) -> Response {
+    let chunk_size = data.config.file_chunk_size as usize;
+    let Ok(modified) = metadata.modified() else {
+        return Response::new(Status::Ok).load_file(path, chunk_size);
+    };
+    let mut loaded = None;
+    let content = file_cache::get_or_load(path, modified, || {
+        let resp = Response::new(Status::Ok).load_file(path, chunk_size);
+        let content = match (resp.status(), resp.content()) {
+            (Status::Ok, Some(content)) => Some(content.to_vec()),
+            _ => None,
+        };
+        loaded = Some(resp);
+        content
+    });
+    match (loaded, content) {
+        (Some(resp), _) => resp,
+        (None, Some(content)) => {
+            let mut resp = Response::new(Status::Ok);
+            resp.add_file_content(path, content, modified);
+            resp
+        }
+        // Loading failed for the request which did it; this one answers for itself.
+        (None, None) => Response::new(Status::Ok).load_file(path, chunk_size),
+    }
+}
+
+fn sidecar_path(path: &Path, extension: &str) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(extension);
+    sidecar.into()
+}
+
+fn handle_head_request(data: &Data, request: &Request, body: &mut Body<'_>) -> Response {
+    let get_response = handle_get_request(data, request, body);
     get_response.to_head()
 }
 
-fn redirect_dir(path: &Path, data: &Data) -> Response {
+/// Tells the methods allowed for a resource or, for the asterisk-form target, what the server
+/// supports as a whole.
+fn handle_options_request(data: &Data, request: &Request, _body: &mut Body<'_>) -> Response {
+    if request.path == "*" {
+        let mut resp = Response::new(Status::Ok);
+        resp.set_header("Allow", allowed_methods(data));
+        resp.set_header("Accept-Ranges", "bytes");
+        return resp;
+    }
+    let mut resp = Response::new(Status::NoContent);
+    resp.set_header("Allow", allowed_methods(data));
+    resp
+}
+
+/// Echoes the request back, so clients can see what reached the server. As the echo could
+/// reveal credentials to scripts (cross-site tracing), it leaves them out; still, TRACE is off
+/// unless enabled.
+fn handle_trace_request(_data: &Data, request: &Request, _body: &mut Body<'_>) -> Response {
+    const SENSITIVE: [&str; 3] = ["authorization", "cookie", "proxy-authorization"];
+    let mut message = format!(
+        "{} {} HTTP/1.{}\r\n",
+        request.method, request.path, request.version
+    )
+    .into_bytes();
+    for (name, values) in &request.headers {
+        if SENSITIVE.contains(&name.as_str()) {
+            continue;
+        }
+        for value in values {
+            message.extend_from_slice(name.as_bytes());
+            message.extend_from_slice(b": ");
+            message.extend_from_slice(value);
+            message.extend_from_slice(b"\r\n");
+        }
+    }
+    let mut resp = Response::new(Status::Ok);
+    resp.add_content(message);
+    resp.set_header("Content-Type", "message/http");
+    resp
+}
+
+/// Redirects a request for a directory to the same path with a trailing slash, keeping the query.
+fn redirect_dir(path: &str, query: Option<&str>, data: &Data) -> Response {
     info!("Redirecting");
 
     let mut resp = Response::new(Status::Moved);
-    let Some(path) = path.to_str() else {
-        return load_error(Status::BadRequest, data);
+    let base_path = data.config.base_path.as_deref().unwrap_or_default();
+    // A location starting with `//` would name another host, so leading slashes are collapsed.
+    let trimmed = path.trim_start_matches('/');
+    let slash = if trimmed.len() < path.len() { "/" } else { "" };
+    // Hosts may be served on several ports, or behind a proxy under another scheme and port, so
+    // the client resolves the location against the URL it used.
+    let location = match query {
+        Some(query) => format!("{base_path}{slash}{trimmed}/?{query}"),
+        None => format!("{base_path}{slash}{trimmed}/"),
     };
-    let index_location = format!(
-        "http://{}:{}{}/index.html",
-        data.hostname, data.config.port, path
-    );
-    resp.set_header("Location", index_location);
+    resp.set_header("Location", location);
     resp
 }
 
+/// Serves the index.html of a directory, which must not lead out of the content directory.
+fn serve_index(dir: &Path, data: &Data, request: &Request) -> Response {
+    match std::fs::canonicalize(dir.join("index.html")) {
+        Ok(index) if index.starts_with(&data.content_dir) && index.is_file() => {
+            serve_file(&index, data, request)
+        }
+        Ok(index) if !index.starts_with(&data.content_dir) => load_error(Status::Forbidden, data),
+        Ok(_) => load_error(Status::NotFound, data),
+        Err(err) if is_fd_exhaustion(&err) => out_of_descriptors(),
+        Err(_) => load_error(Status::NotFound, data),
+    }
+}
+
 fn load_error(status: Status, data: &Data) -> Response {
     info!("loading error");
     let mut response = Response::new(status);
     let error_file = get_error_page(&status, data);
     if let Some(path) = error_file {
-        response.load_file(path.as_path())
+        response.load_file(path.as_path(), data.config.file_chunk_size as usize)
+    } else if let Some(template) = &data.config.default_error_template {
+        let page = template
+            .replace("{{status}}", &status.code().to_string())
+            .replace("{{reason}}", status.reason());
+        response.add_content(page);
+        response.set_header("Content-Type", "text/html; charset=utf-8");
+        response
     } else {
         response.add_content(format!("Error: {}", status.code()));
         response
     }
 }
 
+/// Finds the page for the status: the one configured for it, if any, or else `{code}.html` in the
+/// host's or the root content directory.
 pub fn get_error_page(status: &Status, data: &Data) -> Option<PathBuf> {
+    let configured = &data.config.error_pages;
+    if let Some((_, path)) = configured.iter().find(|(code, _)| *code == status.code()) {
+        return Some(path.clone());
+    }
     let file_name = status.code().to_string() + ".html";
     let file_name = PathBuf::from(file_name);
 
@@ -1,17 +1,123 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, TcpListener};
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
-pub fn match_file_type(filename: &Path) -> String {
-    let guess = mime_guess::from_path(filename);
-    let mime = match guess.first() {
-        None => mime_guess::mime::APPLICATION_OCTET_STREAM,
-        Some(mime) => mime,
+use ipnet::IpNet;
+use socket2::{Domain, Socket, Type};
+
+use crate::http::Request;
+
+/// Types `mime_guess` gets wrong or doesn't know, which browsers insist on.
+const BUILTIN_MIME_TYPES: [(&str, &str); 2] =
+    [("wasm", "application/wasm"), ("mjs", "text/javascript")];
+
+/// Content types configured by extension, and the one of files no type is known for.
+struct MimeTypes {
+    overrides: HashMap<String, mime_guess::Mime>,
+    default: mime_guess::Mime,
+}
+
+static MIME_TYPES: OnceLock<MimeTypes> = OnceLock::new();
+
+/// Makes files with the given (lowercase) extensions served as the paired content types,
+/// whatever `mime_guess` says, and files of unknown types served as `default`.
+pub fn init_mime_types(overrides: &[(String, mime_guess::Mime)], default: mime_guess::Mime) {
+    let types = MimeTypes {
+        overrides: overrides.iter().cloned().collect(),
+        default,
     };
-    let mime = if mime == mime_guess::mime::TEXT_PLAIN {
-        mime_guess::mime::TEXT_PLAIN_UTF_8
+    // A second initialization keeps the first types.
+    let _ = MIME_TYPES.set(types);
+}
+
+pub fn match_file_type(filename: &Path) -> String {
+    let extension = filename
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    let overridden = extension.as_ref().and_then(|extension| {
+        let configured = MIME_TYPES
+            .get()
+            .and_then(|types| types.overrides.get(extension));
+        configured.cloned().or_else(|| {
+            let (_, mime) = BUILTIN_MIME_TYPES
+                .iter()
+                .find(|(ext, _)| ext == extension)?;
+            mime.parse().ok()
+        })
+    });
+    let mime = overridden.unwrap_or_else(|| {
+        mime_guess::from_path(filename).first().unwrap_or_else(|| {
+            MIME_TYPES
+                .get()
+                .map_or(mime_guess::mime::APPLICATION_OCTET_STREAM, |types| {
+                    types.default.clone()
+                })
+        })
+    });
+    if is_textual(&mime) && mime.get_param(mime_guess::mime::CHARSET).is_none() {
+        format!("{mime}; charset=utf-8")
     } else {
-        mime
+        mime.to_string()
+    }
+}
+
+/// Whether the type is text, which browsers should decode as UTF-8 rather than guess.
+fn is_textual(mime: &mime_guess::Mime) -> bool {
+    use mime_guess::mime;
+    mime.type_() == mime::TEXT
+        || mime
+            .suffix()
+            .is_some_and(|suffix| suffix == mime::JSON || suffix == mime::XML)
+        || [mime::JSON, mime::JAVASCRIPT, mime::XML].contains(&mime.subtype())
+}
+
+/// Codings listed in an `Accept-Encoding` header value, lowercased, with their quality values;
+/// the most preferred come first, in the order listed among equally preferred ones. Entries with
+/// invalid quality values are dropped.
+pub fn parse_accept_encoding(header: &[u8]) -> Vec<(String, f32)> {
+    let header = String::from_utf8_lossy(header);
+    let mut accepted: Vec<_> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut params = entry.split(';');
+            let name = params.next().unwrap_or_default().trim();
+            if name.is_empty() {
+                return None;
+            }
+            let quality = params.find_map(|param| {
+                let param = param.trim();
+                param
+                    .strip_prefix("q=")
+                    .or_else(|| param.strip_prefix("Q="))
+            });
+            let quality = match quality {
+                None => 1.0,
+                Some(q) => q
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|q| (0.0..=1.0).contains(q))?,
+            };
+            Some((name.to_ascii_lowercase(), quality))
+        })
+        .collect();
+    accepted.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    accepted
+}
+
+/// Quality parsed `Accept-Encoding` entries give the content coding, by name or by `*`, with 0
+/// meaning it's not acceptable; `None` if neither is listed.
+pub fn encoding_quality(accepted: &[(String, f32)], coding: &str) -> Option<f32> {
+    let quality_of = |name: &str| {
+        accepted
+            .iter()
+            .find(|(listed, _)| listed.eq_ignore_ascii_case(name))
+            .map(|(_, quality)| *quality)
     };
-    mime.to_string()
+    quality_of(coding).or_else(|| quality_of("*"))
 }
 
 pub fn path_if_existing(path: PathBuf) -> Option<PathBuf> {
@@ -21,3 +127,209 @@ pub fn path_if_existing(path: PathBuf) -> Option<PathBuf> {
         None
     }
 }
+
+/// Percent-decodes a request path, unless it then holds NUL or other control bytes, backslashes
+/// or `..` segments, which could confuse the filesystem or escape the content directory, or isn't
+/// UTF-8.
+pub fn decode_safe_path(path: &str) -> Option<String> {
+    let decoded = percent_decode(path.as_bytes());
+    let safe = !decoded.iter().any(|&b| b.is_ascii_control() || b == b'\\')
+        && !decoded
+            .split(|&b| b == b'/')
+            .any(|segment| segment == b"..");
+    String::from_utf8(decoded).ok().filter(|_| safe)
+}
+
+/// Decodes `%XX` escapes, keeping malformed ones as they are.
+fn percent_decode(bytes: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if let Some(byte) = escaped {
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    decoded
+}
+
+/// Extracts the lowercased hostname from a `Host` header value, dropping the port and the
+/// trailing dot of a fully-qualified name. Returns `None` if the value is malformed.
+pub fn parse_host(value: &[u8]) -> Option<String> {
+    let value = std::str::from_utf8(value).ok()?.trim();
+    if let Some(rest) = value.strip_prefix('[') {
+        let (literal, port) = rest.split_once(']')?;
+        if !port.is_empty() && !is_port(port.strip_prefix(':')?) {
+            return None;
+        }
+        literal.parse::<Ipv6Addr>().ok()?;
+        return Some(format!("[{}]", literal.to_ascii_lowercase()));
+    }
+
+    let host = match value.rsplit_once(':') {
+        Some((host, port)) if is_port(port) => host,
+        Some(_) => return None,
+        None => value,
+    };
+    let host = host.strip_suffix('.').unwrap_or(host);
+    let valid = host.split('.').all(|label| {
+        !label.is_empty()
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    });
+    valid.then(|| host.to_ascii_lowercase())
+}
+
+fn is_port(port: &str) -> bool {
+    !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Compares byte strings in time depending only on their lengths, not on their contents.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Decides whether a client may connect: a peer matching any `deny` network is refused, and when
+/// `allow` is not empty, so is every peer outside of it.
+pub fn is_peer_allowed(peer: IpAddr, allow: &[IpNet], deny: &[IpNet]) -> bool {
+    // IPv4 clients of a dual-stack listener show up as IPv4-mapped IPv6 addresses.
+    let peer = peer.to_canonical();
+    !deny.iter().any(|net| net.contains(&peer))
+        && (allow.is_empty() || allow.iter().any(|net| net.contains(&peer)))
+}
+
+/// Whether the peer is one of the trusted proxies.
+pub fn is_trusted_proxy(peer: IpAddr, trusted: &[IpNet]) -> bool {
+    let peer = peer.to_canonical();
+    trusted.iter().any(|net| net.contains(&peer))
+}
+
+/// The address of the client a request comes from. Behind a trusted proxy, that is the last
+/// X-Forwarded-For entry, added by the proxy itself; other peers could forge the header, so it is
+/// ignored for them.
+pub fn client_ip(peer: IpAddr, request: &Request, trusted: &[IpNet]) -> IpAddr {
+    if !is_trusted_proxy(peer, trusted) {
+        return peer;
+    }
+    request
+        .header_values("X-Forwarded-For")
+        .last()
+        .and_then(|value| value.rsplit(|&b| b == b',').next())
+        .and_then(|entry| std::str::from_utf8(entry).ok())
+        .and_then(|entry| entry.trim().parse().ok())
+        .unwrap_or(peer)
+}
+
+/// Parses a network in CIDR notation, or a single address standing for a network of its own.
+pub fn parse_network(value: &str) -> Result<IpNet, String> {
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("Invalid network: {value}"))
+}
+
+/// Whether the error means the process or the system ran out of file descriptors.
+#[cfg(unix)]
+pub fn is_fd_exhaustion(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::EMFILE | libc::ENFILE))
+}
+
+/// Whether the error means the process or the system ran out of file descriptors.
+#[cfg(not(unix))]
+pub fn is_fd_exhaustion(_err: &io::Error) -> bool {
+    false
+}
+
+/// Raises the soft limit of open file descriptors to `target`, as far as the hard limit allows.
+/// Returns the limit in effect afterwards.
+#[cfg(unix)]
+pub fn raise_open_files_limit(target: u64) -> io::Result<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid `rlimit` for the call to fill in.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let wanted = target.min(limit.rlim_max);
+    if wanted > limit.rlim_cur {
+        limit.rlim_cur = wanted;
+        // SAFETY: `limit` is a valid `rlimit`, with the soft limit not above the hard one.
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(limit.rlim_cur)
+}
+
+/// Raises the soft limit of open file descriptors, which only Unix systems have.
+#[cfg(not(unix))]
+pub fn raise_open_files_limit(_target: u64) -> io::Result<u64> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Binds a listener to the address. On an IPv6 address, it takes IPv4 connections as well
+/// (dual-stack), unless `ipv6_only` is set.
+pub fn bind_listener(address: SocketAddr, ipv6_only: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+    if address.is_ipv6() {
+        socket.set_only_v6(ipv6_only)?;
+    }
+    // As with `TcpListener::bind`, so restarts don't wait for old connections to time out.
+    socket.set_reuse_address(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(128)?;
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nets(values: &[&str]) -> Vec<IpNet> {
+        values.iter().map(|v| parse_network(v).unwrap()).collect()
+    }
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn peers_are_matched_against_ipv4_networks() {
+        let allow = nets(&["10.0.0.0/8"]);
+        let deny = nets(&["10.1.0.0/16"]);
+        assert!(is_peer_allowed(ip("10.2.3.4"), &allow, &deny));
+        assert!(!is_peer_allowed(ip("10.1.3.4"), &allow, &deny));
+        assert!(!is_peer_allowed(ip("192.168.0.1"), &allow, &deny));
+        assert!(is_peer_allowed(ip("192.168.0.1"), &[], &deny));
+    }
+
+    #[test]
+    fn peers_are_matched_against_ipv6_networks() {
+        let allow = nets(&["2001:db8::/32"]);
+        let deny = nets(&["2001:db8:bad::/48", "::1"]);
+        assert!(is_peer_allowed(ip("2001:db8:1::1"), &allow, &deny));
+        assert!(!is_peer_allowed(ip("2001:db8:bad::1"), &allow, &deny));
+        assert!(!is_peer_allowed(ip("2001:db9::1"), &allow, &deny));
+        assert!(!is_peer_allowed(ip("::1"), &[], &deny));
+    }
+
+    #[test]
+    fn ipv4_mapped_peers_count_as_ipv4() {
+        let allow = nets(&["127.0.0.0/8"]);
+        let deny = nets(&["127.0.0.2"]);
+        assert!(is_peer_allowed(ip("::ffff:127.0.0.1"), &allow, &deny));
+        assert!(!is_peer_allowed(ip("::ffff:127.0.0.2"), &allow, &deny));
+        assert!(!is_peer_allowed(ip("::ffff:10.0.0.1"), &allow, &[]));
+    }
+}
@@ -0,0 +1,31 @@
+//! Watching content directories during development, so changes show up in the logs and the file
+//! cache never serves stale files.
+
+use std::path::Path;
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{info, warn};
+
+use crate::file_cache;
+
+/// Starts watching the directories in the background, until the returned watcher is dropped.
+pub fn start<'a>(dirs: impl IntoIterator<Item = &'a Path>) -> notify::Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(|event: notify::Result<Event>| match event {
+        // Reading files is no change.
+        Ok(Event {
+            kind: EventKind::Access(_),
+            ..
+        }) => {}
+        Ok(event) => {
+            for path in &event.paths {
+                info!(kind = ?event.kind, path = %path.display(), "Content changed");
+                file_cache::invalidate(path);
+            }
+        }
+        Err(err) => warn!("Watching content failed: {err}"),
+    })?;
+    for dir in dirs {
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+    }
+    Ok(watcher)
+}
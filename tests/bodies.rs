@@ -0,0 +1,102 @@
+mod common;
+
+use common::{Response, TestServer};
+
+/// Statuses of the responses received one after another.
+fn statuses(mut bytes: &[u8]) -> Vec<u16> {
+    let mut statuses = Vec::new();
+    while !bytes.is_empty() {
+        let response = Response::parse(bytes);
+        let length: usize = response.header("Content-Length").unwrap().parse().unwrap();
+        statuses.push(response.status);
+        bytes = &bytes[bytes.len() - response.body.len() + length..];
+    }
+    statuses
+}
+
+#[test]
+fn chunked_body_is_read_to_its_end() {
+    let server = TestServer::start(&[("index.html", "index")], &[]);
+    let request = b"POST /index.html HTTP/1.1\r\nHost: localhost\r\n\
+        Transfer-Encoding: chunked\r\n\r\n\
+        5\r\nhello\r\n7;ext=1\r\n, world\r\n10\r\n0123456789abcdef\r\n0\r\nTrailer: x\r\n\r\n\
+        GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    // The request following the body is read where the body ends.
+    assert_eq!(statuses(&server.send(request)), [405, 200]);
+}
+
+#[test]
+fn malformed_chunk_size_is_bad_request() {
+    let server = TestServer::start(&[("index.html", "index")], &[]);
+    for size in ["x", "", "-1", "fffffffffffffffffff"] {
+        let request = format!(
+            "POST /index.html HTTP/1.1\r\nHost: localhost\r\n\
+            Transfer-Encoding: chunked\r\n\r\n{size}\r\nhello\r\n0\r\n\r\n"
+        );
+        let response = Response::parse(&server.send(request.as_bytes()));
+        assert_eq!(response.status, 400, "{size:?}");
+    }
+}
+
+#[test]
+fn ambiguous_framing_is_bad_request() {
+    let server = TestServer::start(&[("index.html", "index")], &[]);
+    for framing in [
+        "Content-Length: 5\r\nTransfer-Encoding: chunked\r\n",
+        "Transfer-Encoding: chunked\r\nContent-Length: 5\r\n",
+        "Content-Length: 5\r\nContent-Length: 6\r\n",
+        "Content-Length: 5, 6\r\n",
+        "Content-Length: +5\r\n",
+        "Content-Length: +0\r\n",
+    ] {
+        let request = format!(
+            "POST /index.html HTTP/1.1\r\nHost: localhost\r\n{framing}\r\n5\r\nhello\r\n0\r\n\r\n"
+        );
+        let response = Response::parse(&server.send(request.as_bytes()));
+        assert_eq!(response.status, 400, "{framing:?}");
+    }
+    // Repeating the same length is harmless.
+    let request = b"POST /index.html HTTP/1.1\r\nHost: localhost\r\n\
+        Content-Length: 5\r\nContent-Length: 5\r\n\r\nhello\
+        GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+    assert_eq!(statuses(&server.send(request)), [405, 200]);
+}
+
+#[test]
+fn lengths_over_4_gib_are_well_formed() {
+    let server = TestServer::start(&[("index.html", "index")], &[]);
+    let request = b"POST /index.html HTTP/1.1\r\nHost: localhost\r\n\
+        Content-Length: 5000000000\r\nConnection: close\r\n\r\n";
+    // Refused for the method, not as malformed.
+    let response = Response::parse(&server.send(request));
+    assert_eq!(response.status, 405);
+}
+
+#[test]
+fn transfer_codings_must_end_with_a_single_chunked() {
+    let server = TestServer::start(&[("index.html", "index")], &[]);
+    let status = |codings: &str| {
+        let request = format!(
+            "POST /index.html HTTP/1.1\r\nHost: localhost\r\n{codings}Connection: close\r\n\r\n\
+            5\r\nhello\r\n0\r\n\r\n"
+        );
+        Response::parse(&server.send(request.as_bytes())).status
+    };
+    for codings in [
+        "Transfer-Encoding: gzip\r\n",
+        "Transfer-Encoding: chunked, gzip\r\n",
+        "Transfer-Encoding: chunked\r\nTransfer-Encoding: gzip\r\n",
+        "Transfer-Encoding: chunked, chunked\r\n",
+        "Transfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n",
+    ] {
+        assert_eq!(status(codings), 400, "{codings:?}");
+    }
+    // Well framed, but with a coding the server can't undo.
+    for codings in [
+        "Transfer-Encoding: gzip, chunked\r\n",
+        "Transfer-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n",
+    ] {
+        assert_eq!(status(codings), 501, "{codings:?}");
+    }
+    assert_eq!(status("Transfer-Encoding: Chunked\r\n"), 405);
+}
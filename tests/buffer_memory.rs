@@ -0,0 +1,23 @@
+//! The buffer memory budget is shared by every server in the process, so its tests run in a
+//! binary of their own.
+
+mod common;
+
+use common::{Response, TestServer};
+
+#[test]
+fn requests_over_the_buffer_budget_are_refused() {
+    let server = TestServer::start(&[("index.html", "")], &["--max-buffer-memory", "1024"]);
+    let request = |headers: &str| {
+        let request = format!(
+            "GET /index.html HTTP/1.1\r\nHost: localhost\r\n{headers}Connection: close\r\n\r\n"
+        );
+        Response::parse(&server.send(request.as_bytes())).status
+    };
+    assert_eq!(request(""), 200);
+    // Kept under twice the budget, so the server has read all of it when refusing it.
+    let large = format!("X: {}\r\n", "y".repeat(1500));
+    assert_eq!(request(&large), 503);
+    // The refused request's buffer is given back.
+    assert_eq!(request(""), 200);
+}
@@ -0,0 +1,177 @@
+//! Harness booting the server on an ephemeral port to send it real requests.
+// Each test crate uses only some of the helpers.
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+use clap::Parser;
+use webserver::server::{serve, ShutdownHandle};
+use webserver::Config;
+
+/// A server running in the background on a content directory of its own, shut down and
+/// removed once dropped.
+pub struct TestServer {
+    pub address: SocketAddr,
+    pub content: PathBuf,
+    shutdown: ShutdownHandle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// Serves the files, given as paths relative to the `localhost` host directory and their
+    /// content, with the extra command line arguments.
+    pub fn start(files: &[(&str, &str)], args: &[&str]) -> TestServer {
+        let content = temp_dir();
+        let host_dir = content.join("localhost");
+        for (path, data) in files {
+            let path = host_dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, data).unwrap();
+        }
+        fs::create_dir_all(&host_dir).unwrap();
+
+        let mut config = Config::parse_from(
+            [
+                "webserver",
+                content.to_str().unwrap(),
+                "--bind",
+                "127.0.0.1",
+                "--port",
+                "0",
+                "--no-log-file",
+            ]
+            .iter()
+            .chain(args),
+        );
+        config.resolve_error_pages().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            serve(config, |addresses, shutdown| {
+                tx.send((addresses[0], shutdown)).unwrap();
+            })
+            .unwrap();
+        });
+        let (address, shutdown) = rx.recv().expect("Server failed to start");
+        TestServer {
+            address,
+            content,
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+
+    /// Sends raw bytes on a new connection and returns everything received until it's closed.
+    pub fn send(&self, request: &[u8]) -> Vec<u8> {
+        let mut stream = TcpStream::connect(self.address).unwrap();
+        stream.write_all(request).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        response
+    }
+
+    /// Sends a request with the method and target on its own connection, closed after it.
+    pub fn request(&self, method: &str, target: &str, headers: &[&str]) -> Response {
+        let mut request = format!("{method} {target} HTTP/1.1\r\nHost: localhost\r\n");
+        for header in headers {
+            request.push_str(header);
+            request.push_str("\r\n");
+        }
+        request.push_str("Connection: close\r\n\r\n");
+        Response::parse(&self.send(request.as_bytes()))
+    }
+
+    pub fn get(&self, target: &str) -> Response {
+        self.request("GET", target, &[])
+    }
+
+    /// Shuts the server down and waits until it has stopped.
+    pub fn stop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop();
+        let _ = fs::remove_dir_all(&self.content);
+    }
+}
+
+/// A response as received, with header names lowercased.
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn parse(bytes: &[u8]) -> Response {
+        let end = bytes
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("Incomplete response head");
+        let head = std::str::from_utf8(&bytes[..end]).unwrap();
+        let mut lines = head.split("\r\n");
+        let status = lines.next().unwrap().split(' ').nth(1).unwrap();
+        let headers = lines
+            .map(|line| {
+                let (name, value) = line.split_once(':').unwrap();
+                (name.to_ascii_lowercase(), value.trim().to_owned())
+            })
+            .collect();
+        Response {
+            status: status.parse().unwrap(),
+            headers,
+            body: bytes[end + 4..].to_vec(),
+        }
+    }
+
+    /// Reads one response off the connection, leaving it open; its content must have a length.
+    pub fn read_from(stream: &mut TcpStream) -> Response {
+        let mut bytes = Vec::new();
+        let mut byte = [0];
+        while !bytes.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            bytes.push(byte[0]);
+        }
+        let mut response = Response::parse(&bytes);
+        let length = response
+            .header("Content-Length")
+            .map_or(0, |v| v.parse().unwrap());
+        response.body = vec![0; length];
+        stream.read_exact(&mut response.body).unwrap();
+        response
+    }
+
+    /// The first value of the header.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_ascii_lowercase();
+        self.headers
+            .iter()
+            .find(|(header, _)| *header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Creates an empty directory, unique within the test run.
+pub fn temp_dir() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = format!(
+        "webserver-test-{}-{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let dir = std::env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.canonicalize().unwrap()
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use std::fs;
+
+use clap::Parser;
+use common::temp_dir;
+use webserver::{get_hosts, Config};
+
+#[test]
+fn worker_threads_keeps_old_flag_name() {
+    let config = Config::parse_from(["webserver", ".", "-p", "80", "--worker-threads", "2"]);
+    assert_eq!(config.worker_threads, 2);
+    let config = Config::parse_from([
+        "webserver",
+        ".",
+        "-p",
+        "80",
+        "--threads-per-connection",
+        "3",
+    ]);
+    assert_eq!(config.worker_threads, 3);
+}
+
+#[test]
+fn host_names_are_lowercased() {
+    let config = Config::parse_from(["webserver", ".", "-p", "80", "--host", "Example.COM=."]);
+    assert_eq!(config.hosts[0].0, "example.com");
+}
+
+#[test]
+fn overlapping_roots_are_refused_with_isolated_hosts() {
+    let content = temp_dir();
+    fs::create_dir_all(content.join("localhost/nested")).unwrap();
+    let nested = format!("nested.test={}", content.join("localhost/nested").display());
+    let args = [
+        "webserver",
+        content.to_str().unwrap(),
+        "--bind",
+        "127.0.0.1",
+        "--port",
+        "0",
+        "--host",
+        &nested,
+    ];
+    let config = Config::parse_from(args);
+    assert!(get_hosts(&config).is_ok());
+    let config = Config::parse_from(args.iter().chain(&["--isolate-hosts"]));
+    let Err(err) = get_hosts(&config) else {
+        panic!("Overlapping roots were accepted");
+    };
+    assert!(err.contains("overlap"), "{err}");
+    fs::remove_dir_all(content).unwrap();
+}
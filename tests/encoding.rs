@@ -0,0 +1,103 @@
+mod common;
+
+use common::TestServer;
+
+const FILES: [(&str, &str); 3] = [
+    ("file.txt", "identity"),
+    ("file.txt.br", "brotli"),
+    ("file.txt.gz", "gzip"),
+];
+
+fn fetch(server: &TestServer, accept_encoding: &str) -> (Option<String>, Vec<u8>) {
+    let header = format!("Accept-Encoding: {accept_encoding}");
+    let response = server.request("GET", "/file.txt", &[&header]);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    let coding = response.header("Content-Encoding").map(str::to_owned);
+    (coding, response.body)
+}
+
+#[test]
+fn brotli_is_preferred() {
+    let server = TestServer::start(&FILES, &[]);
+    let (coding, body) = fetch(&server, "gzip, deflate, br");
+    assert_eq!(coding.as_deref(), Some("br"));
+    assert_eq!(body, b"brotli");
+}
+
+#[test]
+fn gzip_without_brotli() {
+    let server = TestServer::start(&FILES, &[]);
+    assert_eq!(fetch(&server, "gzip").0.as_deref(), Some("gzip"));
+    assert_eq!(fetch(&server, "gzip, br;q=0").0.as_deref(), Some("gzip"));
+}
+
+#[test]
+fn quality_values_decide() {
+    let server = TestServer::start(&FILES, &[]);
+    assert_eq!(fetch(&server, "br;q=0.5, gzip").0.as_deref(), Some("gzip"));
+    assert_eq!(
+        fetch(&server, "*;q=0.1, gzip;q=0.2").0.as_deref(),
+        Some("gzip")
+    );
+    assert_eq!(fetch(&server, "*").0.as_deref(), Some("br"));
+}
+
+#[test]
+fn identity_when_nothing_else_fits() {
+    let server = TestServer::start(&FILES, &[]);
+    assert_eq!(fetch(&server, "deflate"), (None, b"identity".to_vec()));
+    assert_eq!(fetch(&server, "br;q=0.5, identity").0, None);
+}
+
+#[test]
+fn refused_identity_is_not_acceptable() {
+    let server = TestServer::start(&FILES, &[]);
+    let headers = ["Accept-Encoding: deflate, identity;q=0"];
+    assert_eq!(server.request("GET", "/file.txt", &headers).status, 406);
+    let headers = ["Accept-Encoding: gzip, *;q=0"];
+    assert_eq!(server.request("GET", "/file.txt", &headers).status, 200);
+}
+
+#[test]
+fn refused_identity_without_variants_is_not_acceptable() {
+    let server = TestServer::start(&[("plain.txt", "identity")], &[]);
+    let headers = ["Accept-Encoding: identity;q=0"];
+    let response = server.request("GET", "/plain.txt", &headers);
+    assert_eq!(response.status, 406);
+    assert_eq!(server.request("HEAD", "/plain.txt", &headers).status, 406);
+    // Without the header, or with identity merely unlisted, the file is sent as it is.
+    assert_eq!(server.get("/plain.txt").status, 200);
+    let headers = ["Accept-Encoding: gzip, br"];
+    assert_eq!(server.request("GET", "/plain.txt", &headers).status, 200);
+}
+
+#[test]
+fn sidecar_keeps_type_of_original() {
+    let server = TestServer::start(&[("page.html", "<p>"), ("page.html.gz", "gzip")], &[]);
+    let response = server.request("GET", "/page.html", &["Accept-Encoding: gzip"]);
+    assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+    assert_eq!(
+        response.header("Content-Type"),
+        Some("text/html; charset=utf-8")
+    );
+    assert_eq!(response.body, b"gzip");
+}
+
+#[test]
+fn no_sidecar_without_accept_encoding() {
+    let server = TestServer::start(&FILES, &[]);
+    let response = server.get("/file.txt");
+    assert_eq!(response.header("Content-Encoding"), None);
+    assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    assert_eq!(response.body, b"identity");
+}
+
+#[test]
+fn no_vary_without_sidecars() {
+    let server = TestServer::start(&[("plain.txt", "identity")], &[]);
+    let response = server.request("GET", "/plain.txt", &["Accept-Encoding: gzip, br"]);
+    assert_eq!(response.header("Content-Encoding"), None);
+    assert_eq!(response.header("Vary"), None);
+    assert_eq!(response.body, b"identity");
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use common::TestServer;
+
+#[test]
+fn health_path_answers_with_query() {
+    let server = TestServer::start(&[("healthz", "file")], &["--health-path", "/healthz"]);
+    for target in ["/healthz", "/healthz?probe=1"] {
+        let response = server.get(target);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"OK\n");
+    }
+    assert_eq!(server.get("/healthz/more").status, 404);
+}
+
+#[test]
+fn metrics_path_answers_with_query() {
+    let server = TestServer::start(&[("metrics", "file")], &["--metrics-path", "/metrics"]);
+    for target in ["/metrics", "/metrics?x"] {
+        let response = server.get(target);
+        assert_eq!(response.status, 200);
+        let body = String::from_utf8(response.body).unwrap();
+        assert!(body.contains("webserver_requests_total"), "{body}");
+    }
+}
+
+#[test]
+fn basic_auth_scheme_is_case_insensitive() {
+    let args = ["--auth-user", "user", "--auth-pass", "pass"];
+    let server = TestServer::start(&[("index.html", "")], &args);
+    let response = server.get("/index.html");
+    assert_eq!(response.status, 401);
+    assert!(response.header("WWW-Authenticate").is_some());
+    // "user:pass" in base64.
+    for scheme in ["Basic", "basic", "BASIC"] {
+        let authorization = format!("Authorization: {scheme} dXNlcjpwYXNz");
+        let response = server.request("GET", "/index.html", &[&authorization]);
+        assert_eq!(response.status, 200);
+    }
+    let wrong = server.request("GET", "/index.html", &["Authorization: Basic dXNlcjpub3Bl"]);
+    assert_eq!(wrong.status, 401);
+}
+
+#[test]
+fn cors_preflight_needs_no_credentials() {
+    let args = [
+        "--auth-user",
+        "user",
+        "--auth-pass",
+        "pass",
+        "--cors-origin",
+        "*",
+    ];
+    let server = TestServer::start(&[("index.html", "")], &args);
+    let preflight = [
+        "Origin: https://example.com",
+        "Access-Control-Request-Method: GET",
+    ];
+    let response = server.request("OPTIONS", "/index.html", &preflight);
+    assert_eq!(response.status, 204);
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+    assert_eq!(server.request("OPTIONS", "/index.html", &[]).status, 401);
+}
+
+#[test]
+fn early_responses_have_cors_headers() {
+    let args = [
+        "--auth-user",
+        "user",
+        "--auth-pass",
+        "pass",
+        "--cors-origin",
+        "https://example.com",
+        "--health-path",
+        "/healthz",
+    ];
+    let server = TestServer::start(&[("index.html", "")], &args);
+    let origin = "Origin: https://example.com";
+    for (target, status) in [("/index.html", 401), ("/healthz", 200)] {
+        let response = server.request("GET", target, &[origin]);
+        assert_eq!(response.status, status);
+        assert_eq!(
+            response.header("Access-Control-Allow-Origin"),
+            Some("https://example.com")
+        );
+        assert_eq!(response.header("Vary"), Some("Origin"));
+    }
+}
+
+#[test]
+fn maintenance_file_switches_the_whole_server() {
+    let sentinel = common::temp_dir().join("maintenance");
+    let args = ["--maintenance-file", sentinel.to_str().unwrap()];
+    let server = TestServer::start(&[("index.html", "up")], &args);
+    assert_eq!(server.get("/index.html").status, 200);
+    std::fs::write(&sentinel, "").unwrap();
+    assert_eq!(server.get("/index.html").status, 503);
+    assert_eq!(server.get("/missing.html").status, 503);
+    std::fs::remove_file(&sentinel).unwrap();
+    assert_eq!(server.get("/index.html").status, 200);
+}
+
+#[test]
+fn maintenance_paths_match_the_decoded_path() {
+    let sentinel = common::temp_dir().join("maintenance");
+    std::fs::write(&sentinel, "").unwrap();
+    let args = [
+        "--maintenance-file",
+        sentinel.to_str().unwrap(),
+        "--maintenance-path",
+        "/admin",
+    ];
+    let files = [("index.html", "up"), ("admin/x.html", "admin")];
+    let server = TestServer::start(&files, &args);
+    for target in [
+        "/admin",
+        "/admin?x",
+        "/admin/x.html",
+        "/%61dmin/x.html",
+        "//admin/x.html",
+        "/./admin/x.html",
+    ] {
+        assert_eq!(server.get(target).status, 503, "{target}");
+    }
+    assert_eq!(server.get("/index.html").status, 200);
+    assert_eq!(server.get("/administrator").status, 404);
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use clap::Parser;
+use common::{temp_dir, TestServer};
+use webserver::{Config, Server};
+
+#[test]
+fn configured_error_page_is_served() {
+    let files = [("pages/missing.html", "gone"), ("404.html", "host page")];
+    let server = TestServer::start(
+        &files,
+        &["--error-page", "404=localhost/pages/missing.html"],
+    );
+    let response = server.get("/nothing.html");
+    assert_eq!(response.status, 404);
+    assert_eq!(response.body, b"gone");
+}
+
+#[test]
+fn host_error_page_is_served_for_other_statuses() {
+    let files = [("pages/missing.html", "gone"), ("400.html", "bad")];
+    let server = TestServer::start(
+        &files,
+        &["--error-page", "404=localhost/pages/missing.html"],
+    );
+    let response = server.get("/%2e%2e/index.html");
+    assert_eq!(response.status, 400);
+    assert_eq!(response.body, b"bad");
+}
+
+#[test]
+fn plain_error_without_pages() {
+    let server = TestServer::start(&[("index.html", "")], &[]);
+    let response = server.get("/nothing.html");
+    assert_eq!(response.status, 404);
+    assert_eq!(response.body, b"Error: 404");
+}
+
+#[test]
+fn server_refuses_missing_error_page() {
+    let content = temp_dir();
+    std::fs::create_dir(content.join("localhost")).unwrap();
+    let config = Config::parse_from([
+        "webserver",
+        content.to_str().unwrap(),
+        "--port",
+        "0",
+        "--error-page",
+        "404=missing.html",
+    ]);
+    let err = Server::new(config).err().unwrap();
+    assert!(err.starts_with("Invalid error page for 404"), "{err}");
+    std::fs::remove_dir_all(content).unwrap();
+}
+
+#[test]
+fn server_resolves_error_pages_against_content() {
+    let content = temp_dir();
+    std::fs::create_dir(content.join("localhost")).unwrap();
+    std::fs::write(content.join("404.html"), "").unwrap();
+    let config = Config::parse_from([
+        "webserver",
+        content.to_str().unwrap(),
+        "--port",
+        "0",
+        "--error-page",
+        "404=404.html",
+    ]);
+    assert!(Server::new(config).is_ok());
+    std::fs::remove_dir_all(content).unwrap();
+}
+
+/// Writes a default error template outside of any content directory.
+fn error_template() -> String {
+    let path = temp_dir().join("error.html");
+    std::fs::write(&path, "<h1>{{status}} {{reason}}</h1>").unwrap();
+    path.to_str().unwrap().to_owned()
+}
+
+#[test]
+fn default_error_template_is_filled_in() {
+    let template = error_template();
+    let server = TestServer::start(
+        &[("index.html", "")],
+        &["--default-error-template", &template],
+    );
+    let response = server.get("/nothing.html");
+    assert_eq!(response.status, 404);
+    assert_eq!(response.body, b"<h1>404 Not Found</h1>");
+    assert_eq!(
+        response.header("Content-Type"),
+        Some("text/html; charset=utf-8")
+    );
+    let response = server.get("/%2e%2e/index.html");
+    assert_eq!(response.status, 400);
+    assert_eq!(response.body, b"<h1>400 Bad Request</h1>");
+}
+
+#[test]
+fn error_pages_take_precedence_over_the_default_template() {
+    let template = error_template();
+    let files = [("pages/missing.html", "gone"), ("400.html", "bad")];
+    let server = TestServer::start(
+        &files,
+        &[
+            "--default-error-template",
+            &template,
+            "--error-page",
+            "404=localhost/pages/missing.html",
+        ],
+    );
+    assert_eq!(server.get("/nothing.html").body, b"gone");
+    assert_eq!(server.get("/%2e%2e/index.html").body, b"bad");
+}
@@ -0,0 +1,90 @@
+//! The file cache is process-wide, so its tests share one configuration of it.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use common::TestServer;
+use webserver::file_cache;
+
+const CACHE_ARGS: [&str; 4] = [
+    "--cache-size-bytes",
+    "1024",
+    "--cache-max-file-bytes",
+    "1024",
+];
+
+#[test]
+fn concurrent_misses_load_once() {
+    file_cache::init(1024, 1024);
+    let path = Path::new("/content/popular.html");
+    let modified = SystemTime::now();
+    let loads = AtomicUsize::new(0);
+    let barrier = Barrier::new(8);
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                scope.spawn(|| {
+                    barrier.wait();
+                    file_cache::get_or_load(path, modified, || {
+                        loads.fetch_add(1, Ordering::Relaxed);
+                        thread::sleep(Duration::from_millis(200));
+                        Some(b"popular".to_vec())
+                    })
+                })
+            })
+            .collect();
+        for worker in workers {
+            assert_eq!(worker.join().unwrap(), Some(b"popular".to_vec()));
+        }
+    });
+    assert_eq!(loads.load(Ordering::Relaxed), 1);
+    assert_eq!(file_cache::get(path, modified), Some(b"popular".to_vec()));
+}
+
+#[test]
+fn modified_files_are_read_again() {
+    let server = TestServer::start(&[("page.html", "old")], &CACHE_ARGS);
+    let path = server
+        .content
+        .join("localhost/page.html")
+        .canonicalize()
+        .unwrap();
+    assert_eq!(server.get("/page.html").body, b"old");
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+    assert_eq!(file_cache::get(&path, modified), Some(b"old".to_vec()));
+
+    fs::write(&path, "new").unwrap();
+    let file = fs::File::options().write(true).open(&path).unwrap();
+    file.set_modified(modified + Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(server.get("/page.html").body, b"new");
+}
+
+#[test]
+fn watched_changes_invalidate_cached_files() {
+    let args = [&CACHE_ARGS[..], &["--watch"]].concat();
+    let server = TestServer::start(&[("watched.html", "old")], &args);
+    let path = server
+        .content
+        .join("localhost/watched.html")
+        .canonicalize()
+        .unwrap();
+    assert_eq!(server.get("/watched.html").body, b"old");
+    let modified = fs::metadata(&path).unwrap().modified().unwrap();
+
+    // With its modification time kept, only the watcher tells the change apart.
+    fs::write(&path, "new").unwrap();
+    let file = fs::File::options().write(true).open(&path).unwrap();
+    file.set_modified(modified).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while server.get("/watched.html").body != b"new" {
+        assert!(Instant::now() < deadline, "cached file never invalidated");
+        thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use std::io::Read;
+use std::net::TcpStream;
+
+use common::{Response, TestServer};
+
+fn status(server: &TestServer, forwarded_for: &str) -> u16 {
+    let header = format!("X-Forwarded-For: {forwarded_for}");
+    server.request("GET", "/index.html", &[&header]).status
+}
+
+#[test]
+fn trusted_proxy_names_the_client() {
+    let args = ["--trusted-proxy", "127.0.0.0/8", "--deny", "10.0.0.66"];
+    let server = TestServer::start(&[("index.html", "")], &args);
+    assert_eq!(status(&server, "10.0.0.66"), 403);
+    // Only the entry the proxy added itself counts; earlier ones could be forged.
+    assert_eq!(status(&server, "10.0.0.66, 10.0.0.1"), 200);
+    assert_eq!(status(&server, "10.0.0.1, 10.0.0.66"), 403);
+    assert_eq!(status(&server, "not an address"), 200);
+}
+
+#[test]
+fn forwarded_for_of_untrusted_peers_is_ignored() {
+    let args = ["--trusted-proxy", "10.9.9.9", "--deny", "10.0.0.66"];
+    let server = TestServer::start(&[("index.html", "")], &args);
+    assert_eq!(status(&server, "10.0.0.66"), 200);
+    // Untrusted peers are checked by their own address, before any request is read.
+    let server = TestServer::start(&[("index.html", "")], &["--allow", "10.0.0.1"]);
+    let mut stream = TcpStream::connect(server.address).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    assert_eq!(Response::parse(&response).status, 403);
+}
+
+#[test]
+fn forwarded_clients_are_rate_limited_apart() {
+    let args = ["--trusted-proxy", "127.0.0.1", "--rate-limit", "1"];
+    let server = TestServer::start(&[("index.html", "")], &args);
+    assert_eq!(status(&server, "10.0.0.1"), 200);
+    assert_eq!(status(&server, "10.0.0.2"), 200);
+    assert_eq!(status(&server, "10.0.0.1"), 429);
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use common::TestServer;
+
+fn server_header(args: &[&str]) -> Option<String> {
+    let server = TestServer::start(&[("index.html", "")], args);
+    let found = server
+        .get("/index.html")
+        .header("Server")
+        .map(str::to_owned);
+    // Error responses carry it the same way.
+    let missing = server
+        .get("/missing.html")
+        .header("Server")
+        .map(str::to_owned);
+    assert_eq!(found, missing, "{args:?}");
+    found
+}
+
+#[test]
+fn server_header_names_the_server_by_default() {
+    assert_eq!(
+        server_header(&[]).as_deref(),
+        Some("Telpenarmo's webserver")
+    );
+    let version = format!("webserver/{}", env!("CARGO_PKG_VERSION"));
+    assert_eq!(
+        server_header(&["--server-tokens", "version"]),
+        Some(version)
+    );
+}
+
+#[test]
+fn server_header_can_be_renamed_or_left_out() {
+    assert_eq!(
+        server_header(&["--server-name", "Apache"]).as_deref(),
+        Some("Apache")
+    );
+    assert_eq!(server_header(&["--no-server-header"]), None);
+    assert_eq!(server_header(&["--server-tokens", "off"]), None);
+}
+
+#[test]
+fn configured_headers_are_on_every_response() {
+    let args = [
+        "--hsts",
+        "--no-sniff",
+        "--frame-options",
+        "DENY",
+        "--header",
+        "Content-Security-Policy: default-src 'self'",
+    ];
+    let server = TestServer::start(&[("index.html", "")], &args);
+    for target in ["/index.html", "/missing.html"] {
+        let response = server.get(target);
+        let expected = [
+            ("Strict-Transport-Security", "max-age=31536000"),
+            ("X-Content-Type-Options", "nosniff"),
+            ("X-Frame-Options", "DENY"),
+            ("Content-Security-Policy", "default-src 'self'"),
+        ];
+        for (name, value) in expected {
+            assert_eq!(response.header(name), Some(value), "{target} {name}");
+        }
+    }
+}
+
+#[test]
+fn configured_headers_replace_others() {
+    let args = [
+        "--security-headers",
+        "--header",
+        "x-frame-options: DENY",
+        "--header",
+        "Content-Type: text/x-custom",
+    ];
+    let server = TestServer::start(&[("index.html", "")], &args);
+    let response = server.get("/index.html");
+    for (name, value) in [
+        ("X-Frame-Options", "DENY"),
+        ("Content-Type", "text/x-custom"),
+    ] {
+        let values: Vec<_> = response
+            .headers
+            .iter()
+            .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect();
+        assert_eq!(values, [value], "{name}");
+    }
+    assert_eq!(
+        response.header("Referrer-Policy"),
+        Some("strict-origin-when-cross-origin")
+    );
+}
+
+#[test]
+fn repr_digest_is_the_sha_256_of_the_whole_file() {
+    const HELLO: &str = "sha-256=:LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=:";
+    const GOODBYE: &str = "sha-256=:guNaY866N+lkZDTF3UEupXcUfx5KQczeFhQlMYfj2/k=:";
+    // Loaded into memory, and sent straight from disk.
+    for threshold in ["0", "1"] {
+        let args = ["--repr-digest", "--sendfile-threshold", threshold];
+        let server = TestServer::start(&[("hello.txt", "hello")], &args);
+        assert_eq!(server.get("/hello.txt").header("Repr-Digest"), Some(HELLO));
+        // A range still carries the digest of the whole representation.
+        let range = server.request("GET", "/hello.txt", &["Range: bytes=1-2"]);
+        assert_eq!(range.status, 206);
+        assert_eq!(range.header("Repr-Digest"), Some(HELLO));
+        // A changed file isn't given the digest of what it was.
+        std::fs::write(server.content.join("localhost/hello.txt"), "goodbye").unwrap();
+        assert_eq!(
+            server.get("/hello.txt").header("Repr-Digest"),
+            Some(GOODBYE)
+        );
+    }
+}
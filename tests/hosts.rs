@@ -0,0 +1,86 @@
+mod common;
+
+use std::fs;
+use std::net::SocketAddr;
+
+use clap::Parser;
+use common::{temp_dir, Response, TestServer};
+use webserver::{get_hosts, Config, HostData};
+
+#[test]
+fn get_hosts_finds_host_directories() {
+    let content = temp_dir();
+    fs::create_dir(content.join("localhost")).unwrap();
+    let config = Config::parse_from([
+        "webserver",
+        content.to_str().unwrap(),
+        "--bind",
+        "127.0.0.1",
+        "--port",
+        "8123",
+        "--listen",
+        "127.0.0.1:8124",
+    ]);
+
+    let hosts = get_hosts(&config).unwrap();
+    assert_eq!(hosts.len(), 1);
+    let host = &hosts[0];
+    assert_eq!(host.get_hostname(), "localhost");
+    let expected: Vec<SocketAddr> = vec![
+        "127.0.0.1:8124".parse().unwrap(),
+        "127.0.0.1:8123".parse().unwrap(),
+    ];
+    assert_eq!(host.get_addresses(), expected);
+    assert!(std::ptr::eq(host.get_config(), &config));
+    fs::remove_dir_all(content).unwrap();
+}
+
+#[test]
+fn http_1_1_requires_host_header() {
+    let server = TestServer::start(&[("index.html", "hello")], &[]);
+
+    let response = server.send(b"GET /index.html HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert_eq!(Response::parse(&response).status, 400);
+
+    let response =
+        server.send(b"GET /index.html HTTP/1.1\r\nhOsT: localhost\r\nConnection: close\r\n\r\n");
+    let response = Response::parse(&response);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"hello");
+
+    let response = Response::parse(&server.send(b"GET /index.html HTTP/1.0\r\n\r\n"));
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"hello");
+
+    // Requests naming two hosts are refused, even with the same name twice.
+    for second in ["example.com", "localhost"] {
+        let request = format!(
+            "GET /index.html HTTP/1.1\r\nHost: localhost\r\nHost: {second}\r\n\
+            Connection: close\r\n\r\n"
+        );
+        let response = server.send(request.as_bytes());
+        assert_eq!(Response::parse(&response).status, 400, "{second}");
+    }
+}
+
+#[test]
+fn fully_qualified_host_names_route_to_their_host() {
+    let other = temp_dir();
+    fs::write(other.join("index.html"), "example").unwrap();
+    let host = format!("example.com={}", other.to_str().unwrap());
+    let server = TestServer::start(&[("index.html", "localhost")], &["--host", &host]);
+    let get = |host: &str| {
+        let request =
+            format!("GET /index.html HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+        Response::parse(&server.send(request.as_bytes()))
+    };
+    for host in ["example.com.", "Example.COM.", "example.com.:8080"] {
+        let response = get(host);
+        assert_eq!(response.status, 200, "{host}");
+        assert_eq!(response.body, b"example", "{host}");
+    }
+    for host in ["example.com..", "example..com", ".example.com"] {
+        assert_eq!(get(host).status, 400, "{host}");
+    }
+    fs::remove_dir_all(other).unwrap();
+}
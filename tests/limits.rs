@@ -0,0 +1,57 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use common::{Response, TestServer};
+
+#[test]
+fn trickled_head_times_out() {
+    let server = TestServer::start(&[("index.html", "")], &["--header-timeout", "1"]);
+    let mut stream = TcpStream::connect(server.address).unwrap();
+    stream.write_all(b"GET /index.html HTTP/1.1\r\n").unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    let start = Instant::now();
+    let mut response = Vec::new();
+    // Every byte comes well within any idle timeout, but the head never ends.
+    while response.is_empty() && start.elapsed() < Duration::from_secs(5) {
+        stream.write_all(b"X: y\r\n").unwrap();
+        let mut received = [0; 1024];
+        if let Ok(count) = stream.read(&mut received) {
+            response.extend_from_slice(&received[..count]);
+        }
+    }
+    stream.set_read_timeout(None).unwrap();
+    stream.read_to_end(&mut response).unwrap();
+    assert_eq!(Response::parse(&response).status, 408);
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn heads_over_the_size_limit_are_refused() {
+    let server = TestServer::start(&[("index.html", "")], &["--max-header-bytes", "1024"]);
+    let request = |headers: &str| {
+        let request = format!(
+            "GET /index.html HTTP/1.1\r\nHost: localhost\r\n{headers}Connection: close\r\n\r\n"
+        );
+        Response::parse(&server.send(request.as_bytes())).status
+    };
+    assert_eq!(request("X: y\r\n"), 200);
+    // Heads are kept under twice the limit, so the server has read all of them by the time it
+    // refuses them; closing with some left unread could reset the connection.
+    // One value alone too large, even with the head left unfinished.
+    let large = format!("X: {}\r\n", "y".repeat(1200));
+    assert_eq!(request(&large), 431);
+    let mut stream = TcpStream::connect(server.address).unwrap();
+    let partial = format!("GET /index.html HTTP/1.1\r\nHost: localhost\r\n{large}");
+    stream.write_all(partial.as_bytes()).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    assert_eq!(Response::parse(&response).status, 431);
+    // Small headers adding up to too much.
+    let many = "X: yyyyyyyy\r\n".repeat(100);
+    assert_eq!(request(&many), 431);
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+use clap::Parser;
+use common::{temp_dir, Response};
+use webserver::server::{serve, ShutdownHandle};
+use webserver::utils::bind_listener;
+use webserver::Config;
+
+/// A server of host directories, each holding an `index.html` with the host's name.
+struct Listening {
+    addresses: Vec<SocketAddr>,
+    content: PathBuf,
+    shutdown: ShutdownHandle,
+    thread: Option<JoinHandle<Result<(), String>>>,
+}
+
+impl Listening {
+    fn start(hosts: &[&str], args: &[&str]) -> Listening {
+        let content = temp_dir();
+        for host in hosts {
+            fs::create_dir(content.join(host)).unwrap();
+            fs::write(content.join(host).join("index.html"), host).unwrap();
+        }
+        let config = Config::parse_from(
+            ["webserver", content.to_str().unwrap(), "--no-log-file"]
+                .iter()
+                .chain(args),
+        );
+        let (tx, rx) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            serve(config, |addresses, shutdown| {
+                tx.send((addresses.to_vec(), shutdown)).unwrap();
+            })
+        });
+        let (addresses, shutdown) = rx.recv().expect("Server failed to start");
+        Listening {
+            addresses,
+            content,
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Listening {
+    fn drop(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = fs::remove_dir_all(&self.content);
+    }
+}
+
+fn get_index(address: SocketAddr, host: &str) -> Response {
+    let mut stream = TcpStream::connect(address).unwrap();
+    let request = format!("GET /index.html HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    Response::parse(&response)
+}
+
+#[test]
+fn every_listen_address_gets_a_listener() {
+    let args = [
+        "--bind",
+        "127.0.0.1",
+        "--port",
+        "0",
+        "--listen",
+        "127.0.0.2:0",
+        "--listen",
+        "127.0.0.3:0",
+    ];
+    let server = Listening::start(&["localhost"], &args);
+    let mut ips: Vec<_> = server
+        .addresses
+        .iter()
+        .map(|a| a.ip().to_string())
+        .collect();
+    ips.sort();
+    assert_eq!(ips, ["127.0.0.1", "127.0.0.2", "127.0.0.3"]);
+    for &address in &server.addresses {
+        let response = get_index(address, "localhost");
+        assert_eq!(response.status, 200, "{address}");
+        assert_eq!(response.body, b"localhost");
+    }
+}
+
+#[test]
+fn bind_replaces_resolving_hostnames() {
+    // `.invalid` never resolves, so the host is served only thanks to the override.
+    let server = Listening::start(
+        &["site.invalid", "localhost"],
+        &["--bind", "127.0.0.1", "--port", "0"],
+    );
+    assert_eq!(server.addresses.len(), 1);
+    assert_eq!(server.addresses[0].ip().to_string(), "127.0.0.1");
+    for host in ["site.invalid", "localhost"] {
+        assert_eq!(get_index(server.addresses[0], host).body, host.as_bytes());
+    }
+}
+
+#[test]
+fn ipv6_loopback_is_served_over_ipv6() {
+    let server = Listening::start(&["localhost"], &["--bind", "::1", "--port", "0"]);
+    let address = server.addresses[0];
+    assert_eq!(address.ip().to_string(), "::1");
+    let response = get_index(address, "localhost");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"localhost");
+}
+
+#[test]
+fn ipv6_listeners_take_ipv4_unless_ipv6_only() {
+    for ipv6_only in [false, true] {
+        let listener = bind_listener("[::]:0".parse().unwrap(), ipv6_only).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connected = TcpStream::connect(("127.0.0.1", port)).is_ok();
+        assert_eq!(connected, !ipv6_only, "ipv6_only: {ipv6_only}");
+    }
+}
@@ -0,0 +1,91 @@
+//! Logging is set up once per process, so its tests run apart.
+
+mod common;
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use clap::Parser;
+use common::temp_dir;
+use time::{OffsetDateTime, UtcOffset};
+use webserver::logging::{self, RotatingFile};
+use webserver::Config;
+
+#[test]
+fn unwritable_log_dir_leaves_stdout_logging() {
+    let content = temp_dir();
+    // A file where the log directory should be created keeps it from being created.
+    let blocker = content.join("blocker");
+    fs::write(&blocker, "").unwrap();
+    let log_dir = blocker.join("logs");
+    let config = Config::parse_from([
+        "webserver",
+        content.to_str().unwrap(),
+        "--port",
+        "0",
+        "--log-dir",
+        log_dir.to_str().unwrap(),
+    ]);
+    assert!(logging::init(&config).is_ok());
+    tracing::info!("Logged to standard output only");
+    assert!(blocker.is_file());
+    fs::remove_dir_all(content).unwrap();
+}
+
+#[test]
+fn pruning_keeps_recent_log_files_and_others() {
+    let dir = temp_dir();
+    // From the oldest to the most recently modified, with whether pruning keeps them.
+    let files = [
+        ("2020-01-01.log.json", false),
+        ("2020-01-02.log.json", false),
+        ("2020-01-02.1.log.json", true),
+        ("2020-01-03.log.json", true),
+        ("notes.log.json", true),
+        ("2020-1-04.log.json", true),
+        ("2020-01-05.0.log.json", true),
+        ("2020-01-05.txt", true),
+    ];
+    let now = SystemTime::now();
+    for (minutes, (name, _)) in (1..=files.len() as u64).rev().zip(files) {
+        let file = File::create(dir.join(name)).unwrap();
+        file.set_modified(now - Duration::from_secs(minutes * 60))
+            .unwrap();
+    }
+    let log = RotatingFile::open(&dir, 0, 3, UtcOffset::UTC).unwrap();
+    log.prune().unwrap();
+
+    for (name, kept) in files {
+        assert_eq!(dir.join(name).exists(), kept, "{name}");
+    }
+    // Along with them, today's file is the current one.
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 7);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// Seconds since the epoch the test clock tells.
+static NOW: AtomicI64 = AtomicI64::new(0);
+
+fn test_clock() -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp(NOW.load(Ordering::Relaxed)).unwrap()
+}
+
+#[test]
+fn files_rotate_at_local_midnight() {
+    let dir = temp_dir();
+    // 2024-01-31 21:59:59 UTC, a second before midnight two hours east.
+    NOW.store(1_706_738_399, Ordering::Relaxed);
+    let offset = UtcOffset::from_hms(2, 0, 0).unwrap();
+    let mut log = RotatingFile::open_with_clock(&dir, 0, 0, offset, test_clock).unwrap();
+    log.write_all(b"before\n").unwrap();
+    NOW.fetch_add(2, Ordering::Relaxed);
+    log.write_all(b"after\n").unwrap();
+    log.flush().unwrap();
+
+    let read = |name| fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!(read("2024-01-31.log.json"), "before\n");
+    assert_eq!(read("2024-02-01.log.json"), "after\n");
+    fs::remove_dir_all(dir).unwrap();
+}
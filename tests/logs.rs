@@ -0,0 +1,126 @@
+//! Logs are captured by a process-wide subscriber, so the tests checking them run apart.
+
+mod common;
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use common::{Response, TestServer};
+use tracing::Level;
+
+/// Everything logged by the process so far.
+#[derive(Clone, Default)]
+struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Installs the capturing subscriber the first time, returning what it captured.
+fn captured() -> String {
+    static CAPTURE: OnceLock<Capture> = OnceLock::new();
+    let capture = CAPTURE.get_or_init(|| {
+        let capture = Capture::default();
+        let writer = capture.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::set_global_default(subscriber).unwrap();
+        capture
+    });
+    String::from_utf8_lossy(&capture.0.lock().unwrap()).into_owned()
+}
+
+#[test]
+fn malformed_requests_are_logged_escaped_and_truncated() {
+    captured();
+    let server = TestServer::start(&[("index.html", "")], &[]);
+    let mut request = b"GET /\x01index.html HTTP/1.1\r\nHost: localhost\r\n".to_vec();
+    request.extend_from_slice(&[b'x'; 300]);
+    request.extend_from_slice(b"\r\n\r\n");
+    let response = server.send(&request);
+    assert!(response.starts_with(b"HTTP/1.1 400"));
+
+    let logs = captured();
+    let line = logs
+        .lines()
+        .find(|line| line.contains("Malformed request"))
+        .expect("Malformed request not logged");
+    assert!(line.contains("DEBUG"), "{line}");
+    assert!(
+        line.contains(r"GET /\x01index.html HTTP/1.1\r\nHost"),
+        "{line}"
+    );
+    assert!(line.contains("truncated=true"), "{line}");
+    assert!(!line.contains(&"x".repeat(300)), "{line}");
+}
+
+#[test]
+fn slow_requests_are_warned_about() {
+    captured();
+    let content = "x".repeat(8 << 20);
+    let args = [
+        "--slow-request-threshold",
+        "20",
+        "--sendfile-threshold",
+        "0",
+    ];
+    let server = TestServer::start(&[("slow.txt", &content), ("fast.txt", "")], &args);
+    assert_eq!(server.get("/fast.txt").status, 200);
+    // Sending stalls while the client doesn't read, which makes the request slow.
+    let mut stream = TcpStream::connect(server.address).unwrap();
+    stream
+        .write_all(b"GET /slow.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    assert_eq!(Response::parse(&response).body.len(), content.len());
+
+    let logs = captured();
+    // Connections close once their requests are reported, so it is logged by now.
+    let line = logs
+        .lines()
+        .find(|line| line.contains("Slow request") && line.contains("GET /slow.txt"))
+        .expect("Slow request not logged");
+    assert!(line.contains("WARN"), "{line}");
+    assert!(
+        !logs
+            .lines()
+            .any(|line| line.contains("Slow request") && line.contains("/fast.txt")),
+        "{logs}"
+    );
+    for field in ["total_ms=", "handling_ms=", "writing_ms="] {
+        assert!(line.contains(field), "{line}");
+    }
+}
+
+#[test]
+fn completed_requests_are_logged_with_timing_and_size() {
+    captured();
+    let server = TestServer::start(&[("timed.txt", "timed")], &[]);
+    let response =
+        server.send(b"GET /timed.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    assert_eq!(Response::parse(&response).status, 200);
+
+    let logs = captured();
+    let line = logs
+        .lines()
+        .find(|line| line.contains("Request completed") && line.contains("GET /timed.txt"))
+        .expect("Request completion not logged");
+    assert!(line.contains("duration_ms="), "{line}");
+    let bytes_sent = format!("bytes_sent={}", response.len());
+    assert!(line.contains(&bytes_sent), "{line}");
+}
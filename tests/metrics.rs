@@ -0,0 +1,60 @@
+//! Metrics are process-wide, so their tests run in a binary of their own.
+
+mod common;
+
+use std::fs;
+use std::sync::Arc;
+
+use common::{temp_dir, TestServer};
+use webserver::metrics::{self, register_host};
+
+#[test]
+fn hosts_registered_twice_share_counters() {
+    let first = register_host("twice.example");
+    let second = register_host("twice.example");
+    assert!(Arc::ptr_eq(&first, &second));
+    second.record(3, 5);
+    assert_eq!(first.sent(), 5);
+    let rendered = metrics::render();
+    let lines = rendered
+        .lines()
+        .filter(|line| line.contains("host=\"twice.example\""))
+        .count();
+    assert_eq!(lines, 2);
+}
+
+#[test]
+fn host_labels_are_escaped() {
+    register_host("odd\"host\\with\nnewline");
+    let rendered = metrics::render();
+    assert!(
+        rendered.contains(r#"webserver_host_sent_bytes_total{host="odd\"host\\with\nnewline"} 0"#)
+    );
+}
+
+#[test]
+fn traffic_is_counted_per_host() {
+    let (alpha, beta) = (temp_dir(), temp_dir());
+    fs::write(alpha.join("index.html"), "a".repeat(100)).unwrap();
+    fs::write(beta.join("index.html"), "b".repeat(1000)).unwrap();
+    let hosts = [
+        format!("alpha.test={}", alpha.to_str().unwrap()),
+        format!("beta.test={}", beta.to_str().unwrap()),
+    ];
+    let server = TestServer::start(&[], &["--host", &hosts[0], "--host", &hosts[1]]);
+    let mut expected = Vec::new();
+    for (host, requests) in [("alpha.test", 2), ("beta.test", 1)] {
+        let request =
+            format!("GET /index.html HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+        let mut sent = 0;
+        for _ in 0..requests {
+            sent += server.send(request.as_bytes()).len() as u64;
+        }
+        expected.push((host, request.len() as u64 * requests, sent));
+    }
+    for (host, received, sent) in expected {
+        let traffic = register_host(host);
+        assert_eq!(traffic.received(), received, "{host}");
+        assert_eq!(traffic.sent(), sent, "{host}");
+    }
+}
@@ -0,0 +1,55 @@
+//! Content types are configured for the whole process, so one server covers them all.
+
+mod common;
+
+use common::TestServer;
+
+#[test]
+fn configured_content_types() {
+    let files = [
+        ("LICENSE", "MIT"),
+        ("app.wasm", ""),
+        ("data.custom", ""),
+        ("data.json", ""),
+        ("image.png", ""),
+        ("image.svg", ""),
+        ("index.html", ""),
+        ("module.mjs", ""),
+        ("style.css", ""),
+    ];
+    let args = [
+        "--default-mime",
+        "text/plain",
+        "--mime",
+        "custom=application/x-custom",
+    ];
+    let server = TestServer::start(&files, &args);
+    let content_type = |target| server.get(target).header("Content-Type").map(str::to_owned);
+
+    assert_eq!(
+        content_type("/LICENSE").as_deref(),
+        Some("text/plain; charset=utf-8")
+    );
+    assert_eq!(
+        content_type("/data.custom").as_deref(),
+        Some("application/x-custom")
+    );
+    assert_eq!(
+        content_type("/app.wasm").as_deref(),
+        Some("application/wasm")
+    );
+    assert_eq!(content_type("/image.png").as_deref(), Some("image/png"));
+    assert_eq!(
+        content_type("/style.css").as_deref(),
+        Some("text/css; charset=utf-8")
+    );
+    // Text types get a charset, so browsers don't have to guess it; binary ones don't.
+    for (target, expected) in [
+        ("/index.html", "text/html; charset=utf-8"),
+        ("/module.mjs", "text/javascript; charset=utf-8"),
+        ("/data.json", "application/json; charset=utf-8"),
+        ("/image.svg", "image/svg+xml; charset=utf-8"),
+    ] {
+        assert_eq!(content_type(target).as_deref(), Some(expected), "{target}");
+    }
+}
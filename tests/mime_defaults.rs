@@ -0,0 +1,24 @@
+//! Content types are configured for the whole process, so the defaults need one of their own.
+
+mod common;
+
+use common::TestServer;
+
+#[test]
+fn builtin_content_types_apply_without_configuration() {
+    let files = [("app.wasm", ""), ("module.mjs", ""), ("LICENSE", "")];
+    let server = TestServer::start(&files, &[]);
+    let content_type = |target| server.get(target).header("Content-Type").map(str::to_owned);
+    assert_eq!(
+        content_type("/app.wasm").as_deref(),
+        Some("application/wasm")
+    );
+    assert_eq!(
+        content_type("/module.mjs").as_deref(),
+        Some("text/javascript; charset=utf-8")
+    );
+    assert_eq!(
+        content_type("/LICENSE").as_deref(),
+        Some("application/octet-stream")
+    );
+}
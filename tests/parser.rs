@@ -0,0 +1,82 @@
+use webserver::http::Request;
+use webserver::parser::{try_parse, Error};
+
+#[test]
+fn parses_complete_head() {
+    let input = b"GET /index.html?x=1 HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\nbody";
+    let Ok((request, size)) = try_parse(16, input) else {
+        panic!("Request not parsed");
+    };
+    assert_eq!(size, input.len() - 4);
+    assert_eq!(request.method, "GET");
+    assert_eq!(request.path, "/index.html?x=1");
+    assert_eq!(request.version, 1);
+    assert_eq!(request.header("host"), Some(&b"localhost"[..]));
+    assert_eq!(request.header("Accept"), Some(&b"*/*"[..]));
+}
+
+#[test]
+fn incomplete_head_is_partial() {
+    for input in [
+        &b""[..],
+        b"GET / HT",
+        b"GET / HTTP/1.1\r\nHost: localhost\r\n",
+    ] {
+        assert!(matches!(try_parse(16, input), Err(Error::Partial)));
+    }
+}
+
+#[test]
+fn too_many_headers() {
+    let input = b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n";
+    assert!(matches!(try_parse(2, input), Err(Error::TooManyHeaders)));
+    assert!(try_parse(3, input).is_ok());
+}
+
+#[test]
+fn malformed_head_is_syntax_error() {
+    for input in [
+        &b"GET / HTTP/1.1\r\nBad Header\r\n\r\n"[..],
+        b"GET / HTTP/2.0\r\n\r\n",
+        b"G\x01T / HTTP/1.1\r\n\r\n",
+    ] {
+        assert!(matches!(try_parse(16, input), Err(Error::Syntax)));
+    }
+}
+
+#[test]
+fn different_lengths_conflict() {
+    let input = b"POST / HTTP/1.1\r\nContent-Length: 1\r\ncontent-length: 2\r\n\r\n";
+    assert!(matches!(
+        try_parse(16, input),
+        Err(Error::ConflictingLength)
+    ));
+    let repeated = b"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 1\r\n\r\n";
+    assert!(try_parse(16, repeated).is_ok());
+}
+
+#[test]
+fn degenerate_parse_is_not_a_request() {
+    let mut headers = [];
+    assert!(Request::new(httparse::Request::new(&mut headers)).is_none());
+
+    let mut headers = [httparse::EMPTY_HEADER; 4];
+    let mut partial = httparse::Request::new(&mut headers);
+    assert!(partial.parse(b"GET / HT").unwrap().is_partial());
+    assert!(Request::new(partial).is_none());
+}
+
+#[test]
+fn repeated_headers_keep_every_value() {
+    let input = b"GET / HTTP/1.1\r\nCookie: a=1\r\nX-Forwarded-For: 10.0.0.1\r\n\
+        cookie: b=2\r\nX-FORWARDED-FOR: 10.0.0.2\r\n\r\n";
+    let Ok((request, _)) = try_parse(16, input) else {
+        panic!("Request not parsed");
+    };
+    let values: Vec<_> = request.header_values("Cookie").collect();
+    assert_eq!(values, [&b"a=1"[..], b"b=2"]);
+    let values: Vec<_> = request.header_values("x-forwarded-for").collect();
+    assert_eq!(values, [&b"10.0.0.1"[..], b"10.0.0.2"]);
+    assert_eq!(request.header("COOKIE"), Some(&b"a=1"[..]));
+    assert_eq!(request.header_values("Missing").count(), 0);
+}
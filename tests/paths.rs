@@ -0,0 +1,158 @@
+mod common;
+
+use std::fs;
+
+use common::{temp_dir, Response, TestServer};
+
+#[test]
+fn query_is_not_part_of_the_resolved_path() {
+    let server = TestServer::start(&[("a.html", "a"), ("dir/index.html", "index")], &[]);
+    let response = server.get("/a.html?x=1");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"a");
+    assert_eq!(server.get("/dir/?x=1").body, b"index");
+    assert_eq!(server.get("/%61.html").body, b"a");
+}
+
+#[test]
+fn unsafe_paths_are_bad_requests() {
+    let server = TestServer::start(&[("a.html", "a")], &[]);
+    let targets = [
+        "/../a.html",
+        "/%2e%2e/a.html",
+        "/dir/%2E%2E/%2e%2e/etc/passwd",
+        "/a.html%5c",
+        "/dir\\a.html",
+        "/a.html%00",
+        "/a%0a.html",
+        "/%ff.html",
+    ];
+    for target in targets {
+        assert_eq!(server.get(target).status, 400, "{target}");
+    }
+    // Only the path is checked; the query is left to whoever reads it.
+    assert_eq!(server.get("/a.html?next=/../secret").status, 200);
+}
+
+#[test]
+fn targets_not_starting_with_slash_are_bad_requests() {
+    let server = TestServer::start(&[("index.html", "index")], &[]);
+    for target in ["", "index.html", "?x", "*"] {
+        let request = format!("GET {target} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let response = Response::parse(&server.send(request.as_bytes()));
+        assert_eq!(response.status, 400, "{target:?}");
+    }
+    // Extra slashes stay within the content directory.
+    assert_eq!(server.get("//index.html").body, b"index");
+    assert_eq!(server.get("//etc/passwd").status, 404);
+}
+
+#[test]
+fn base_path_is_stripped_and_added_to_redirects() {
+    let files = [("index.html", "index"), ("dir/index.html", "dir index")];
+    let server = TestServer::start(&files, &["--base-path", "/app/"]);
+    for (target, location) in [("/app", "/app/"), ("/app/dir", "/app/dir/")] {
+        let response = server.get(target);
+        assert_eq!(response.status, 301, "{target}");
+        assert_eq!(response.header("Location"), Some(location));
+    }
+    assert_eq!(server.get("/app/").body, b"index");
+    assert_eq!(server.get("/app/dir/").body, b"dir index");
+    assert_eq!(server.get("/app/index.html").body, b"index");
+    for target in ["/", "/index.html", "/apple", "/application/index.html"] {
+        assert_eq!(server.get(target).status, 404, "{target}");
+    }
+}
+
+#[test]
+fn absolute_form_targets_name_path_and_host() {
+    let other = temp_dir();
+    fs::write(other.join("index.html"), "other").unwrap();
+    let host = format!("example.com={}", other.to_str().unwrap());
+    let server = TestServer::start(&[("index.html", "index")], &["--host", &host]);
+
+    assert_eq!(server.get("http://localhost/index.html").body, b"index");
+    assert_eq!(
+        server.get("HTTPS://localhost/index.html?x=1").body,
+        b"index"
+    );
+    // The authority takes the place of the Host header.
+    assert_eq!(server.get("http://example.com/index.html").body, b"other");
+    let response = server.get("http://localhost");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"index");
+    assert_eq!(server.get("http://localhost?x=1").body, b"index");
+    // Other schemes aren't absolute-form targets of this server.
+    assert_eq!(server.get("ftp://localhost/index.html").status, 400);
+
+    let response = server.request("OPTIONS", "*", &[]);
+    assert_eq!(response.status, 200);
+    assert!(response.header("Allow").is_some());
+    fs::remove_dir_all(other).unwrap();
+}
+
+#[test]
+fn redirects_are_relative_to_the_url_used() {
+    let server = TestServer::start(&[("dir/index.html", "index")], &[]);
+    // As when a proxy terminates TLS on another port.
+    let headers = [
+        "X-Forwarded-Proto: https",
+        "X-Forwarded-Host: example.com:8443",
+    ];
+    for target in [
+        "/dir",
+        "//dir",
+        "http://localhost:8080/dir",
+        "http://localhost:8080//dir",
+    ] {
+        let response = server.request("GET", target, &headers);
+        assert_eq!(response.status, 301, "{target}");
+        assert_eq!(response.header("Location"), Some("/dir/"), "{target}");
+    }
+}
+
+#[test]
+fn directories_redirect_to_their_trailing_slash() {
+    let files = [("dir/index.html", "index"), ("evil.com/index.html", "")];
+    let server = TestServer::start(&files, &[]);
+    for (target, location) in [
+        ("/dir", "/dir/"),
+        ("/dir?x=1&y=/z", "/dir/?x=1&y=/z"),
+        ("/d%69r?", "/d%69r/?"),
+        // Not redirected to another host.
+        ("//evil.com", "/evil.com/"),
+        ("///evil.com?x", "/evil.com/?x"),
+    ] {
+        let response = server.get(target);
+        assert_eq!(response.status, 301, "{target}");
+        assert_eq!(response.header("Location"), Some(location), "{target}");
+    }
+    assert_eq!(server.get("/dir/").body, b"index");
+    assert_eq!(server.get("/dir/?x=1").body, b"index");
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_loops_are_missing_resources() {
+    let server = TestServer::start(&[("index.html", "index")], &["--spa-fallback"]);
+    let host_dir = server.content.join("localhost");
+    for name in ["loop", "loop.html"] {
+        std::os::unix::fs::symlink(host_dir.join(name), host_dir.join(name)).unwrap();
+    }
+    // Like any missing path without an extension, it gets the SPA's index.
+    let response = server.get("/loop");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, b"index");
+    let response = server.get("/loop.html");
+    assert_eq!(response.status, 404);
+}
+
+#[test]
+fn paths_through_files_are_missing_resources() {
+    let server = TestServer::start(&[("file.txt", "file")], &[]);
+    for target in ["/file.txt/sub", "/file.txt/sub/deeper.html", "/file.txt/"] {
+        assert_eq!(server.get(target).status, 404, "{target}");
+    }
+    let overlong = format!("/{}.html", "a".repeat(300));
+    assert_eq!(server.get(&overlong).status, 404);
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use std::fs::File;
+use std::time::{Duration, UNIX_EPOCH};
+
+use common::TestServer;
+
+fn status_if_match(server: &TestServer, target: &str, condition: &str) -> u16 {
+    let header = format!("If-Match: {condition}");
+    server.request("GET", target, &[&header]).status
+}
+
+#[test]
+fn if_match_with_current_etag() {
+    let server = TestServer::start(&[("file.txt", "content")], &[]);
+    let etag = server.get("/file.txt").header("ETag").unwrap().to_owned();
+    assert_eq!(status_if_match(&server, "/file.txt", &etag), 200);
+    let listed = format!("\"other\", {etag}");
+    assert_eq!(status_if_match(&server, "/file.txt", &listed), 200);
+    assert_eq!(status_if_match(&server, "/file.txt", "*"), 200);
+}
+
+#[test]
+fn if_match_with_other_etag_fails() {
+    let server = TestServer::start(&[("file.txt", "content")], &[]);
+    assert_eq!(status_if_match(&server, "/file.txt", "\"outdated\""), 412);
+    let header = "If-Match: \"outdated\"";
+    assert_eq!(server.request("HEAD", "/file.txt", &[header]).status, 412);
+}
+
+#[test]
+fn if_match_for_missing_file_is_not_found() {
+    let server = TestServer::start(&[], &[]);
+    assert_eq!(status_if_match(&server, "/missing.txt", "*"), 404);
+}
+
+#[test]
+fn files_sent_from_disk_have_strong_etags() {
+    let large = "x".repeat(2 << 20);
+    let server = TestServer::start(&[("large.txt", &large)], &[]);
+    let response = server.request("HEAD", "/large.txt", &[]);
+    let etag = response.header("ETag").unwrap().to_owned();
+    assert!(etag.starts_with('"'), "{etag}");
+    assert_eq!(status_if_match(&server, "/large.txt", &etag), 200);
+    assert_eq!(status_if_match(&server, "/large.txt", "\"outdated\""), 412);
+
+    let if_range = format!("If-Range: {etag}");
+    let response = server.request("GET", "/large.txt", &["Range: bytes=0-3", &if_range]);
+    assert_eq!(response.status, 206);
+    assert_eq!(response.body, b"xxxx");
+}
+
+#[test]
+fn files_modified_before_the_epoch_are_served() {
+    let large = "x".repeat(2 << 20);
+    let files = [("old.txt", "old"), ("large.txt", large.as_str())];
+    let server = TestServer::start(&files, &[]);
+    let before_epoch = UNIX_EPOCH - Duration::from_secs(86_400);
+    for name in ["old.txt", "large.txt"] {
+        let file = File::options()
+            .write(true)
+            .open(server.content.join("localhost").join(name))
+            .unwrap();
+        file.set_modified(before_epoch).unwrap();
+    }
+    for target in ["/old.txt", "/large.txt"] {
+        let response = server.request("HEAD", target, &[]);
+        assert_eq!(response.status, 200, "{target}");
+        assert_eq!(response.header("Last-Modified"), None);
+        let etag = response.header("ETag").unwrap().to_owned();
+        assert_eq!(status_if_match(&server, target, &etag), 200);
+    }
+}
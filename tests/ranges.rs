@@ -0,0 +1,76 @@
+mod common;
+
+use common::TestServer;
+
+const CONTENT: &str = "0123456789";
+
+#[test]
+fn range_is_partial_content() {
+    let server = TestServer::start(&[("file.txt", CONTENT)], &[]);
+    let response = server.request("GET", "/file.txt", &["Range: bytes=2-4"]);
+    assert_eq!(response.status, 206);
+    assert_eq!(response.header("Content-Range"), Some("bytes 2-4/10"));
+    assert_eq!(response.body, b"234");
+}
+
+#[test]
+fn if_range_with_current_etag_gets_range() {
+    let server = TestServer::start(&[("file.txt", CONTENT)], &[]);
+    let etag = server.get("/file.txt").header("ETag").unwrap().to_owned();
+    let if_range = format!("If-Range: {etag}");
+    let response = server.request("GET", "/file.txt", &["Range: bytes=2-4", &if_range]);
+    assert_eq!(response.status, 206);
+    assert_eq!(response.body, b"234");
+}
+
+#[test]
+fn if_range_with_other_etag_gets_whole_file() {
+    let server = TestServer::start(&[("file.txt", CONTENT)], &[]);
+    let headers = ["Range: bytes=2-4", "If-Range: \"outdated\""];
+    let response = server.request("GET", "/file.txt", &headers);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, CONTENT.as_bytes());
+}
+
+#[test]
+fn if_range_with_modification_date() {
+    let server = TestServer::start(&[("file.txt", CONTENT)], &[]);
+    let modified = server
+        .get("/file.txt")
+        .header("Last-Modified")
+        .unwrap()
+        .to_owned();
+    let if_range = format!("If-Range: {modified}");
+    let response = server.request("GET", "/file.txt", &["Range: bytes=2-4", &if_range]);
+    assert_eq!(response.status, 206);
+
+    let if_range = "If-Range: Thu, 01 Jan 2015 00:00:00 GMT";
+    let response = server.request("GET", "/file.txt", &["Range: bytes=2-4", if_range]);
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn if_range_with_weak_etag_gets_whole_file() {
+    let server = TestServer::start(&[("file.txt", CONTENT)], &["--sendfile-threshold", "1"]);
+    let etag = server.get("/file.txt").header("ETag").unwrap().to_owned();
+    let if_range = format!("If-Range: W/{etag}");
+    let response = server.request("GET", "/file.txt", &["Range: bytes=2-4", &if_range]);
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn only_files_accept_ranges() {
+    let files = [("file.txt", CONTENT), ("dir/index.html", CONTENT)];
+    let server = TestServer::start(&files, &["--health-path", "/healthz"]);
+    for target in ["/file.txt", "/dir/"] {
+        let response = server.get(target);
+        assert_eq!(response.status, 200, "{target}");
+        assert_eq!(response.header("Accept-Ranges"), Some("bytes"), "{target}");
+    }
+    // A redirect, a missing file and generated content can't be served in ranges.
+    for (target, status) in [("/dir", 301), ("/missing.txt", 404), ("/healthz", 200)] {
+        let response = server.get(target);
+        assert_eq!(response.status, status, "{target}");
+        assert_eq!(response.header("Accept-Ranges"), None, "{target}");
+    }
+}
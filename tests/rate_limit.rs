@@ -0,0 +1,31 @@
+mod common;
+
+use std::thread;
+use std::time::Duration;
+
+use common::TestServer;
+
+#[test]
+fn requests_past_the_limit_wait_for_tokens() {
+    let server = TestServer::start(&[("index.html", "")], &["--rate-limit", "2"]);
+    for _ in 0..2 {
+        assert_eq!(server.get("/index.html").status, 200);
+    }
+    let response = server.get("/index.html");
+    assert_eq!(response.status, 429);
+    assert_eq!(response.header("Retry-After"), Some("1"));
+    // Half a second brings back a token at two per second.
+    thread::sleep(Duration::from_millis(600));
+    assert_eq!(server.get("/index.html").status, 200);
+}
+
+#[test]
+fn each_server_has_its_own_limit() {
+    let limited = TestServer::start(&[("index.html", "")], &["--rate-limit", "1"]);
+    let unlimited = TestServer::start(&[("index.html", "")], &[]);
+    assert_eq!(limited.get("/index.html").status, 200);
+    assert_eq!(limited.get("/index.html").status, 429);
+    for _ in 0..5 {
+        assert_eq!(unlimited.get("/index.html").status, 200);
+    }
+}
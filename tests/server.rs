@@ -0,0 +1,479 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use common::{temp_dir, Response, TestServer};
+use webserver::server::{catch_handler_panic, serve};
+use webserver::{Config, Server};
+
+#[test]
+fn serves_existing_file() {
+    let server = TestServer::start(&[("index.html", "<h1>Hello</h1>")], &[]);
+    let response = server.get("/index.html");
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.header("Content-Type"),
+        Some("text/html; charset=utf-8")
+    );
+    assert_eq!(response.header("Content-Length"), Some("14"));
+    assert_eq!(response.body, b"<h1>Hello</h1>");
+}
+
+#[test]
+fn files_larger_than_a_chunk_are_served_intact() {
+    let content: String = (0..100_003u32)
+        .map(|i| char::from(b'a' + (i * 7 % 26) as u8))
+        .collect();
+    // Sent straight from disk, then read into memory first.
+    for threshold in ["1", "0"] {
+        let args = [
+            "--file-chunk-size",
+            "4096",
+            "--sendfile-threshold",
+            threshold,
+        ];
+        let server = TestServer::start(&[("big.txt", &content)], &args);
+        let response = server.get("/big.txt");
+        assert_eq!(response.status, 200, "{threshold}");
+        assert!(response.body == content.as_bytes(), "{threshold}");
+        let response = server.request("GET", "/big.txt", &["Range: bytes=5000-70000"]);
+        assert_eq!(response.status, 206, "{threshold}");
+        assert!(
+            response.body == content.as_bytes()[5000..=70000],
+            "{threshold}"
+        );
+    }
+}
+
+#[test]
+fn head_has_headers_of_get_without_body() {
+    let server = TestServer::start(&[("index.html", "<h1>Hello</h1>")], &[]);
+    let response = server.request("HEAD", "/index.html", &[]);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Length"), Some("14"));
+    assert!(response.body.is_empty());
+}
+
+#[test]
+fn head_mirrors_headers_of_get() {
+    let files = [
+        ("index.html", "<h1>Hello</h1>"),
+        ("dir/index.html", "<h1>Dir</h1>"),
+        ("file.txt", "identity"),
+        ("file.txt.gz", "gzip"),
+    ];
+    let server = TestServer::start(&files, &[]);
+    let cases: [(&str, &[&str]); 5] = [
+        ("/index.html", &[]),
+        ("/dir/", &[]),
+        ("/dir", &[]),
+        ("/missing.html", &[]),
+        ("/file.txt", &["Accept-Encoding: gzip"]),
+    ];
+    // Date may tick between the two responses.
+    let headers = |response: &Response| {
+        let mut headers: Vec<_> = response
+            .headers
+            .iter()
+            .filter(|(name, _)| name != "date")
+            .cloned()
+            .collect();
+        headers.sort();
+        headers
+    };
+    for (target, extra) in cases {
+        let get = server.request("GET", target, extra);
+        let head = server.request("HEAD", target, extra);
+        assert_eq!(head.status, get.status, "{target} {extra:?}");
+        assert_eq!(headers(&head), headers(&get), "{target} {extra:?}");
+        assert!(head.body.is_empty(), "{target} {extra:?}");
+    }
+    // Range is only for GET, so HEAD describes the whole file.
+    let head = server.request("HEAD", "/index.html", &["Range: bytes=0-3"]);
+    assert_eq!(head.status, 200);
+    assert_eq!(head.header("Content-Length"), Some("14"));
+}
+
+#[test]
+fn missing_file_is_not_found() {
+    let server = TestServer::start(&[("index.html", "")], &[]);
+    assert_eq!(server.get("/missing.html").status, 404);
+}
+
+#[test]
+fn unsupported_method_is_not_allowed() {
+    let server = TestServer::start(&[("index.html", "")], &[]);
+    let response = server.request("DELETE", "/index.html", &[]);
+    assert_eq!(response.status, 405);
+    assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS"));
+}
+
+#[test]
+fn unknown_method_is_not_implemented() {
+    let server = TestServer::start(&[("index.html", "")], &[]);
+    assert_eq!(server.request("BREW", "/index.html", &[]).status, 501);
+}
+
+#[test]
+fn server_runs_until_told_to_stop() {
+    let content = temp_dir();
+    std::fs::create_dir(content.join("localhost")).unwrap();
+    let args = ["--bind", "127.0.0.1", "--port", "0", "--no-log-file"];
+    let config = Config::parse_from(["webserver", content.to_str().unwrap()].iter().chain(&args));
+    let (stop, stopped) = mpsc::channel();
+    let server = Server::new(config).unwrap();
+    let server = thread::spawn(move || server.run_until(stopped));
+    stop.send(()).unwrap();
+    assert_eq!(server.join().unwrap(), Ok(()));
+    std::fs::remove_dir_all(content).unwrap();
+}
+
+#[test]
+fn shutdown_lets_responses_in_progress_finish() {
+    // Far more than socket buffers hold, so the response is still being sent when stopping.
+    let big = "x".repeat(16 * 1024 * 1024);
+    let mut server = TestServer::start(&[("big.txt", &big)], &[]);
+    let mut client = TcpStream::connect(server.address).unwrap();
+    client
+        .write_all(b"GET /big.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let stopping = thread::spawn(move || server.stop());
+    thread::sleep(Duration::from_millis(100));
+    assert!(!stopping.is_finished());
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).unwrap();
+    let response = Response::parse(&response);
+    assert_eq!(response.status, 200);
+    assert!(response.body == big.as_bytes());
+    stopping.join().unwrap();
+}
+
+#[test]
+fn shutdown_closes_connections_left_after_timeout() {
+    // Far more than socket buffers hold, so sending it stalls while the client doesn't read.
+    let big = "x".repeat(64 * 1024 * 1024);
+    let mut server = TestServer::start(&[("big.txt", &big)], &["--shutdown-timeout", "1"]);
+    let mut client = TcpStream::connect(server.address).unwrap();
+    client
+        .write_all(b"GET /big.txt HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(200));
+
+    let started = Instant::now();
+    server.stop();
+    let took = started.elapsed();
+    assert!(took >= Duration::from_secs(1), "stopped after {took:?}");
+    assert!(took < Duration::from_secs(5), "stopped after {took:?}");
+}
+
+#[test]
+fn connections_wait_for_a_free_worker_thread() {
+    let server = TestServer::start(&[("index.html", "")], &["--worker-threads", "1"]);
+    // Kept alive, this connection holds the only worker.
+    let mut first = TcpStream::connect(server.address).unwrap();
+    first
+        .write_all(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let (tx, rx) = mpsc::channel();
+    let address = server.address;
+    thread::spawn(move || {
+        let mut second = TcpStream::connect(address).unwrap();
+        let request = b"GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        second.write_all(request).unwrap();
+        let mut response = Vec::new();
+        second.read_to_end(&mut response).unwrap();
+        tx.send(response).unwrap();
+    });
+    assert!(rx.recv_timeout(Duration::from_millis(300)).is_err());
+    drop(first);
+    let response = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200"));
+}
+
+#[test]
+fn options_asterisk_describes_server() {
+    let server = TestServer::start(&[], &[]);
+    let response =
+        server.send(b"OPTIONS * HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    let response = Response::parse(&response);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS"));
+    assert_eq!(response.header("Accept-Ranges"), Some("bytes"));
+    assert_eq!(response.header("Content-Length"), Some("0"));
+}
+
+#[test]
+fn keep_alive_serves_requests_on_one_connection() {
+    let server = TestServer::start(&[("index.html", "index")], &[]);
+    for request in [
+        &b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n"[..],
+        b"GET /index.html HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+    ] {
+        let mut stream = TcpStream::connect(server.address).unwrap();
+        for _ in 0..2 {
+            stream.write_all(request).unwrap();
+            let response = Response::read_from(&mut stream);
+            assert_eq!(response.header("Connection"), Some("keep-alive"));
+            assert_eq!(response.body, b"index");
+        }
+    }
+}
+
+#[test]
+fn http_1_0_closes_by_default() {
+    let server = TestServer::start(&[("index.html", "index")], &[]);
+    let response = server.send(b"GET /index.html HTTP/1.0\r\n\r\n");
+    let response = Response::parse(&response);
+    assert_eq!(response.header("Connection"), Some("close"));
+    assert_eq!(response.body, b"index");
+}
+
+#[test]
+fn connection_closes_after_max_duration() {
+    let server = TestServer::start(&[("index.html", "")], &["--connection-max-duration", "1"]);
+    let mut stream = TcpStream::connect(server.address).unwrap();
+    let connected = Instant::now();
+    let mut responses = 0;
+    // Requests keep coming, so the connection never idles.
+    let closing = loop {
+        let request = b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        stream.write_all(request).unwrap();
+        let mut head = Vec::new();
+        let mut byte = [0];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let response = Response::parse(&head);
+        responses += 1;
+        if response.header("Connection") == Some("close") {
+            break connected.elapsed();
+        }
+        assert!(connected.elapsed() < Duration::from_secs(3));
+        thread::sleep(Duration::from_millis(50));
+    };
+    assert!(
+        closing >= Duration::from_secs(1),
+        "closed after {closing:?}"
+    );
+    assert!(responses > 1);
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn hosts_of_other_listeners_are_not_served() {
+    let content = temp_dir();
+    for host in ["127.0.0.1", "127.0.0.2"] {
+        std::fs::create_dir(content.join(host)).unwrap();
+        std::fs::write(content.join(host).join("index.html"), host).unwrap();
+    }
+    let args = ["--port", "0", "--no-log-file"];
+    let config = Config::parse_from(["webserver", content.to_str().unwrap()].iter().chain(&args));
+    let (tx, rx) = mpsc::channel();
+    let server = thread::spawn(move || {
+        serve(config, |addresses, shutdown| {
+            tx.send((addresses.to_vec(), shutdown)).unwrap();
+        })
+    });
+    let (addresses, shutdown) = rx.recv().unwrap();
+    assert_eq!(addresses.len(), 2);
+    for address in addresses {
+        let other = if address.ip().to_string() == "127.0.0.1" {
+            "127.0.0.2"
+        } else {
+            "127.0.0.1"
+        };
+        let mut stream = TcpStream::connect(address).unwrap();
+        let request =
+            format!("GET /index.html HTTP/1.1\r\nHost: {other}\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let response = Response::parse(&response);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, address.ip().to_string().as_bytes());
+    }
+    shutdown.shutdown();
+    assert_eq!(server.join().unwrap(), Ok(()));
+    std::fs::remove_dir_all(content).unwrap();
+}
+
+#[test]
+fn simple_requests_get_content_alone_when_allowed() {
+    let large = "x".repeat(3 << 20);
+    let files = [
+        ("index.html", "<h1>Hello</h1>"),
+        ("large.txt", large.as_str()),
+    ];
+    let server = TestServer::start(&files, &["--allow-http-0.9"]);
+    assert_eq!(server.send(b"GET /index.html\r\n"), b"<h1>Hello</h1>");
+    // Large files are sent from disk, not read into the response first.
+    let response = server.send(b"GET /large.txt\r\n");
+    assert_eq!(response.len(), large.len());
+    assert!(response == large.as_bytes());
+}
+
+#[test]
+fn simple_requests_are_rejected_by_default() {
+    let server = TestServer::start(&[("index.html", "<h1>Hello</h1>")], &[]);
+    let response = Response::parse(&server.send(b"GET /index.html\r\n"));
+    assert_eq!(response.status, 400);
+}
+
+#[test]
+fn hosts_on_one_address_share_its_listener() {
+    let content = temp_dir();
+    for host in ["localhost", "other.test"] {
+        std::fs::create_dir(content.join(host)).unwrap();
+        std::fs::write(content.join(host).join("index.html"), host).unwrap();
+    }
+    let args = ["--bind", "127.0.0.1", "--port", "0", "--no-log-file"];
+    let config = Config::parse_from(["webserver", content.to_str().unwrap()].iter().chain(&args));
+    let (tx, rx) = mpsc::channel();
+    let server = thread::spawn(move || {
+        serve(config, |addresses, shutdown| {
+            tx.send((addresses.to_vec(), shutdown)).unwrap();
+        })
+    });
+    let (addresses, shutdown) = rx.recv().unwrap();
+    assert_eq!(addresses.len(), 1);
+    // Unknown hosts go to the first host by name.
+    for (host, expected) in [
+        ("other.test", "other.test"),
+        ("localhost", "localhost"),
+        ("unknown.test", "localhost"),
+    ] {
+        let mut stream = TcpStream::connect(addresses[0]).unwrap();
+        let request =
+            format!("GET /index.html HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        assert_eq!(Response::parse(&response).body, expected.as_bytes());
+    }
+    shutdown.shutdown();
+    assert_eq!(server.join().unwrap(), Ok(()));
+    std::fs::remove_dir_all(content).unwrap();
+}
+
+#[test]
+fn trace_echoes_request_without_credentials() {
+    let server = TestServer::start(&[("index.html", "")], &["--enable-trace"]);
+    let headers = [
+        "X-Probe: 1",
+        "Authorization: Basic dXNlcjpwYXNz",
+        "Cookie: id=1",
+    ];
+    let response = server.request("TRACE", "/index.html", &headers);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Type"), Some("message/http"));
+    let echo = String::from_utf8(response.body).unwrap();
+    assert!(echo.starts_with("TRACE /index.html HTTP/1.1\r\n"), "{echo}");
+    assert!(echo.contains("x-probe: 1\r\n"), "{echo}");
+    assert!(!echo.contains("authorization"), "{echo}");
+    assert!(!echo.contains("cookie"), "{echo}");
+}
+
+#[test]
+fn allow_lists_methods_in_order() {
+    // Each server keeps its handlers in a hash map of its own, so one could be ordered by luck.
+    for _ in 0..5 {
+        let server = TestServer::start(&[("index.html", "")], &["--enable-trace"]);
+        let response = server.request("DELETE", "/index.html", &[]);
+        assert_eq!(response.status, 405);
+        assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS, TRACE"));
+        let response = server.request("OPTIONS", "/index.html", &[]);
+        assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS, TRACE"));
+    }
+}
+
+#[test]
+fn trace_is_refused_by_default() {
+    let server = TestServer::start(&[("index.html", "")], &[]);
+    let response = server.request("TRACE", "/index.html", &[]);
+    assert_eq!(response.status, 405);
+    assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS"));
+    assert!(response.body.is_empty());
+}
+
+/// Asserts that while a kept-alive connection to `held` occupies the only worker, a request to
+/// `waiting` is answered only once that connection closes.
+fn assert_waits_for_worker(held: SocketAddr, waiting: SocketAddr) {
+    let mut first = TcpStream::connect(held).unwrap();
+    first
+        .write_all(b"GET /index.html HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut second = TcpStream::connect(waiting).unwrap();
+        let request = b"GET /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        second.write_all(request).unwrap();
+        let mut response = Vec::new();
+        second.read_to_end(&mut response).unwrap();
+        tx.send(response).unwrap();
+    });
+    assert!(rx.recv_timeout(Duration::from_millis(300)).is_err());
+    drop(first);
+    let response = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 200"));
+}
+
+#[test]
+fn single_model_serves_one_connection_at_a_time() {
+    let args = ["--concurrency-model", "single", "--worker-threads", "4"];
+    let server = TestServer::start(&[("index.html", "")], &args);
+    assert_waits_for_worker(server.address, server.address);
+}
+
+#[test]
+fn shared_model_pools_workers_of_all_listeners() {
+    let content = temp_dir();
+    for host in ["127.0.0.1", "127.0.0.2"] {
+        std::fs::create_dir(content.join(host)).unwrap();
+        std::fs::write(content.join(host).join("index.html"), host).unwrap();
+    }
+    let args = [
+        "--port",
+        "0",
+        "--no-log-file",
+        "--concurrency-model",
+        "shared",
+        "--worker-threads",
+        "1",
+    ];
+    let config = Config::parse_from(["webserver", content.to_str().unwrap()].iter().chain(&args));
+    let (tx, rx) = mpsc::channel();
+    let server = thread::spawn(move || {
+        serve(config, |addresses, shutdown| {
+            tx.send((addresses.to_vec(), shutdown)).unwrap();
+        })
+    });
+    let (addresses, shutdown) = rx.recv().unwrap();
+    assert_waits_for_worker(addresses[0], addresses[1]);
+    shutdown.shutdown();
+    assert_eq!(server.join().unwrap(), Ok(()));
+    std::fs::remove_dir_all(content).unwrap();
+}
+
+#[test]
+fn panicking_handler_is_internal_server_error() {
+    let response = catch_handler_panic::<()>(|| panic!("deliberate")).unwrap_err();
+    assert_eq!(response.status().code(), 500);
+    let owned = String::from("deliberate");
+    let response = catch_handler_panic::<()>(|| panic!("{owned}")).unwrap_err();
+    assert_eq!(response.status().code(), 500);
+    assert!(catch_handler_panic(|| 1).is_ok_and(|value| value == 1));
+}
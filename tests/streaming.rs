@@ -0,0 +1,40 @@
+use std::io::Cursor;
+
+use webserver::http::{copy_chunked, Response, Status, Tail};
+
+/// Joins the chunks of a chunked body, checking its framing.
+fn decode_chunked(mut bytes: &[u8]) -> Vec<u8> {
+    let mut content = Vec::new();
+    loop {
+        let end = bytes.windows(2).position(|w| w == b"\r\n").unwrap();
+        let size = usize::from_str_radix(std::str::from_utf8(&bytes[..end]).unwrap(), 16).unwrap();
+        bytes = &bytes[end + 2..];
+        if size == 0 {
+            assert_eq!(bytes, b"\r\n");
+            return content;
+        }
+        content.extend_from_slice(&bytes[..size]);
+        assert_eq!(&bytes[size..size + 2], b"\r\n");
+        bytes = &bytes[size + 2..];
+    }
+}
+
+#[test]
+fn streamed_content_is_sent_in_chunks() {
+    let content: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+    let mut response = Response::new(Status::Ok);
+    response.stream_content(Cursor::new(content.clone()));
+    let (head, tail) = response.render();
+    let head = String::from_utf8(head).unwrap();
+    assert!(
+        !head.to_ascii_lowercase().contains("content-length"),
+        "{head}"
+    );
+    let Some(Tail::Stream(mut stream)) = tail else {
+        panic!("Content not streamed");
+    };
+    let mut sent = Vec::new();
+    let length = copy_chunked(&mut stream, &mut sent).unwrap();
+    assert_eq!(length, sent.len() as u64);
+    assert_eq!(decode_chunked(&sent), content);
+}
@@ -0,0 +1,41 @@
+use webserver::utils::{encoding_quality, parse_accept_encoding};
+
+fn parsed(header: &str) -> Vec<(String, f32)> {
+    parse_accept_encoding(header.as_bytes())
+}
+
+#[test]
+fn accept_encoding_orders_by_quality() {
+    assert_eq!(
+        parsed("gzip;q=0.5, BR;q=1.0, deflate"),
+        [
+            ("br".into(), 1.0),
+            ("deflate".into(), 1.0),
+            ("gzip".into(), 0.5)
+        ]
+    );
+}
+
+#[test]
+fn accept_encoding_drops_invalid_entries() {
+    assert_eq!(
+        parsed("gzip;q=2, br;q=x, , deflate ; Q=0.25"),
+        [("deflate".into(), 0.25)]
+    );
+}
+
+#[test]
+fn identity_can_be_refused() {
+    let accepted = parsed("gzip, identity;q=0");
+    assert_eq!(encoding_quality(&accepted, "identity"), Some(0.0));
+    assert_eq!(encoding_quality(&accepted, "gzip"), Some(1.0));
+    assert_eq!(encoding_quality(&accepted, "br"), None);
+}
+
+#[test]
+fn wildcard_covers_unlisted_codings() {
+    let accepted = parsed("br;q=0, *;q=0.3");
+    assert_eq!(encoding_quality(&accepted, "br"), Some(0.0));
+    assert_eq!(encoding_quality(&accepted, "gzip"), Some(0.3));
+    assert_eq!(encoding_quality(&accepted, "identity"), Some(0.3));
+}
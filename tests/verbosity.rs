@@ -0,0 +1,37 @@
+//! The level in RUST_LOG is read from the environment, so all cases run in one test.
+
+mod common;
+
+use clap::Parser;
+use common::temp_dir;
+use tracing::level_filters::LevelFilter;
+use webserver::logging::stdout_level;
+use webserver::Config;
+
+#[test]
+fn verbosity_sets_stdout_level() {
+    let content = temp_dir();
+    let level = |args: &[&str]| {
+        let config = Config::parse_from(
+            ["webserver", content.to_str().unwrap(), "--port", "0"]
+                .iter()
+                .chain(args),
+        );
+        stdout_level(&config)
+    };
+    std::env::remove_var("RUST_LOG");
+    assert_eq!(level(&[]), LevelFilter::INFO);
+    assert_eq!(level(&["-v"]), LevelFilter::DEBUG);
+    assert_eq!(level(&["-vvv"]), LevelFilter::TRACE);
+    assert_eq!(level(&["-q"]), LevelFilter::WARN);
+    assert_eq!(level(&["--quiet", "--quiet"]), LevelFilter::ERROR);
+    assert_eq!(level(&["-qqqq"]), LevelFilter::OFF);
+
+    // A plain level in RUST_LOG wins; directives it can't use as one are left out.
+    std::env::set_var("RUST_LOG", "error");
+    assert_eq!(level(&["-v"]), LevelFilter::ERROR);
+    std::env::set_var("RUST_LOG", "webserver=debug");
+    assert_eq!(level(&["-q"]), LevelFilter::WARN);
+    std::env::remove_var("RUST_LOG");
+    std::fs::remove_dir_all(content).unwrap();
+}